@@ -1,9 +1,9 @@
-extern crate prost_build;
-
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-
-    prost_build::compile_protos(&["proto/mumble.proto"], &["proto/"])?;
-
-    Ok(())
+extern crate prost_build;
+
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+
+    prost_build::compile_protos(&["proto/mumble.proto"], &["proto/"])?;
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,269 @@
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::identity::{Identity, IdentityStore};
+use crate::mumble::{
+    ConnectionConfig, MumbleClient, DEFAULT_ACTION_TIMEOUT, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_LIVENESS_TIMEOUT, DEFAULT_SYNC_TIMEOUT,
+    DEFAULT_TCP_CONNECT_TIMEOUT
+};
+use crate::proxy::Proxy;
+use crate::reconnect::ReconnectPolicy;
+use crate::resolver::{Resolver, SystemResolver, HAPPY_EYEBALLS_DELAY};
+use crate::tls::{ServerVerification, TlsConfig};
+use crate::transport::Transport;
+use crate::url::MumbleUrl;
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Collects everything needed to log in to a server and produces a
+/// [`MumbleClient`] once the server has finished synchronizing.
+#[derive(Clone)]
+pub struct MumbleClientBuilder {
+    config: ConnectionConfig,
+    stored_identity: Option<(IdentityStore, String)>,
+    connect_timeout: Option<Duration>,
+    channel_path: Vec<String>
+}
+
+impl MumbleClientBuilder {
+
+    pub fn new(address: &str) -> Self {
+        let config = ConnectionConfig {
+            address: address.to_owned(),
+            tls_config: TlsConfig::default(),
+            client_name: None,
+            client_version: None,
+            username: String::new(),
+            password: None,
+            tokens: Vec::new(),
+            opus: true,
+            celt_versions: Vec::new(),
+            reconnect: None,
+            proxy: None,
+            force_tcp_voice: false,
+            resolver: Arc::new(SystemResolver),
+            happy_eyeballs_delay: HAPPY_EYEBALLS_DELAY,
+            tcp_connect_timeout: DEFAULT_TCP_CONNECT_TIMEOUT,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            sync_timeout: DEFAULT_SYNC_TIMEOUT,
+            liveness_timeout: DEFAULT_LIVENESS_TIMEOUT,
+            action_timeout: DEFAULT_ACTION_TIMEOUT,
+            #[cfg(test)]
+            open_stream: None
+        };
+
+        Self {
+            config,
+            stored_identity: None,
+            connect_timeout: None,
+            channel_path: Vec::new()
+        }
+    }
+
+    /// Starts from a `mumble://` URL: its address, credentials and channel
+    /// path, which is joined once connected.
+    pub fn from_url(url: &MumbleUrl) -> Self {
+        let mut builder = Self::new(&url.address());
+
+        if let Some(username) = &url.username {
+            builder.username(username);
+        }
+        if let Some(password) = &url.password {
+            builder.password(password);
+        }
+        builder.channel_path(url.channel_path.clone());
+
+        builder
+    }
+
+    pub fn username(&mut self, username: &str) -> &mut Self {
+        self.config.username = username.to_owned();
+        self
+    }
+
+    pub fn password(&mut self, password: &str) -> &mut Self {
+        self.config.password = Some(password.to_owned());
+        self
+    }
+
+    /// Access tokens used to pass password protected channels.
+    pub fn tokens(&mut self, tokens: Vec<String>) -> &mut Self {
+        self.config.tokens = tokens;
+        self
+    }
+
+    /// Operating system name and version reported to the server.
+    pub fn client_info(&mut self, client_name: &str, client_version: &str) -> &mut Self {
+        self.config.client_name = Some(client_name.to_owned());
+        self.config.client_version = Some(client_version.to_owned());
+        self
+    }
+
+    pub fn opus(&mut self, opus: bool) -> &mut Self {
+        self.config.opus = opus;
+        self
+    }
+
+    pub fn celt_versions(&mut self, celt_versions: Vec<i32>) -> &mut Self {
+        self.config.celt_versions = celt_versions;
+        self
+    }
+
+    pub fn identity(&mut self, identity: Identity) -> &mut Self {
+        self.config.tls_config.identity = Some(identity);
+        self.stored_identity = None;
+        self
+    }
+
+    /// Uses the identity called `name` from `store`, creating it on first use
+    /// so the same certificate is presented on every connection.
+    pub fn stored_identity(&mut self, store: IdentityStore, name: &str) -> &mut Self {
+        self.config.tls_config.identity = None;
+        self.stored_identity = Some((store, name.to_owned()));
+        self
+    }
+
+    pub fn verification(&mut self, verification: ServerVerification) -> &mut Self {
+        self.config.tls_config.verification = verification;
+        self
+    }
+
+    /// Name used for SNI and hostname verification instead of the host part
+    /// of the address.
+    pub fn server_name(&mut self, server_name: &str) -> &mut Self {
+        self.config.tls_config.server_name = Some(server_name.to_owned());
+        self
+    }
+
+    /// Channel names from the root down to join right after connecting.
+    pub fn channel_path(&mut self, channel_path: Vec<String>) -> &mut Self {
+        self.channel_path = channel_path;
+        self
+    }
+
+    /// Re-establish the session with `policy` when the connection drops.
+    pub fn reconnect(&mut self, policy: ReconnectPolicy) -> &mut Self {
+        self.config.reconnect = Some(policy);
+        self
+    }
+
+    /// Tunnels the control connection through `proxy`. Voice is then always
+    /// sent over TCP.
+    pub fn proxy(&mut self, proxy: Proxy) -> &mut Self {
+        self.config.proxy = Some(proxy);
+        self
+    }
+
+    /// Sends voice through the control connection instead of UDP.
+    pub fn force_tcp_voice(&mut self, force_tcp_voice: bool) -> &mut Self {
+        self.config.force_tcp_voice = force_tcp_voice;
+        self
+    }
+
+    /// Looks up the server with `resolver` instead of the system's DNS.
+    pub fn resolver<R: Resolver + 'static>(&mut self, resolver: R) -> &mut Self {
+        self.config.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// How long to wait on one address before also trying the next.
+    pub fn happy_eyeballs_delay(&mut self, delay: Duration) -> &mut Self {
+        self.config.happy_eyeballs_delay = delay;
+        self
+    }
+
+    /// Upper bound on the whole connect sequence, from opening the socket
+    /// until the server sends `ServerSync`.
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// How long resolving the address and opening the TCP connection (or
+    /// the proxy tunnel) may take. Defaults to 10 seconds.
+    pub fn tcp_connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.tcp_connect_timeout = timeout;
+        self
+    }
+
+    /// How long the TLS handshake may take. Defaults to 10 seconds.
+    pub fn handshake_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    /// How long to wait for `ServerSync` after authenticating. Defaults to
+    /// 30 seconds.
+    pub fn sync_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.sync_timeout = timeout;
+        self
+    }
+
+    /// The session is considered dead when the server has not echoed a ping
+    /// for this long, and is re-established or reported as disconnected.
+    /// Pings go out every 10 seconds, or three times per timeout when it is
+    /// shorter than 30 seconds. Defaults to 30 seconds.
+    pub fn liveness_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.liveness_timeout = timeout;
+        self
+    }
+
+    /// How long actions like `join_channel` wait for the server to confirm
+    /// or deny them before failing with `Timeout`. Defaults to 10 seconds.
+    pub fn action_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.config.action_timeout = timeout;
+        self
+    }
+
+    pub async fn connect(&self) -> MumbleResult<MumbleClient> {
+        self.with_timeout(async {
+            let config = self.resolve_config()?;
+            let client = MumbleClient::connect(config).await?;
+            self.join_channel_path(client).await
+        }).await
+    }
+
+    /// Logs in over an already established `stream` instead of dialing the
+    /// address. TLS settings are ignored since the stream is used as is, and
+    /// the session is not re-established if the stream drops.
+    pub async fn connect_with_stream<S: Transport>(&self, stream: S) -> MumbleResult<MumbleClient> {
+        self.with_timeout(async {
+            let mut config = self.resolve_config()?;
+            config.reconnect = None;
+            let client = MumbleClient::connect_with_stream(config, stream).await?;
+            self.join_channel_path(client).await
+        }).await
+    }
+
+    async fn join_channel_path(&self, mut client: MumbleClient) -> MumbleResult<MumbleClient> {
+        if self.channel_path.is_empty() {
+            return Ok(client);
+        }
+
+        // the link names the channel exactly
+        client.join_channel_path(&self.channel_path.join("/"), true).await?;
+
+        Ok(client)
+    }
+
+    async fn with_timeout<F: Future<Output = MumbleResult<MumbleClient>>>(&self, future: F) -> MumbleResult<MumbleClient> {
+        match self.connect_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, future).await {
+                Ok(result) => result,
+                Err(_) => Err(MumbleError::Timeout)
+            },
+            None => future.await
+        }
+    }
+
+    pub(crate) fn resolve_config(&self) -> MumbleResult<ConnectionConfig> {
+
+        let mut config = self.config.clone();
+        if let Some((store, name)) = &self.stored_identity {
+            config.tls_config.identity = Some(store.load_or_create(name)?);
+        }
+
+        Ok(config)
+    }
+}
@@ -1,61 +1,466 @@
-use crate::{common::MumbleResult, mumbleproto::ChannelState};
-
-#[derive(Default, Clone)]
-pub struct ChannelList {
-    channels: Vec<Channel>
-}
-
-impl ChannelList {
-
-    pub fn push(&mut self, channel: Channel) -> MumbleResult<()> {
-        self.channels.push(channel);
-
-        Ok(())
-    }
-
-    pub fn find(&self, name: &str) -> Option<Channel> {
-
-        let channel = self.channels.iter()
-            .find(|&x | { x.name == name });
-
-        if let Some(channel) = channel {
-            let channel = channel.clone();
-            return Some(channel);
-        }
-
-        None
-    }
-}
-
-#[derive(Default, Clone)]
-pub struct Channel {
-    pub id: u32,
-    pub parent: u32,
-    pub name: String,
-}
-
-impl Channel {
-    pub fn from_message(message: &ChannelState) -> MumbleResult<Self> {
-
-        let id = match message.channel_id {
-            Some(id) => id,
-            None => 0
-        };
-
-        let parent = match message.parent {
-            Some(parent) => parent,
-            None => 0
-        };
-
-        let name = match &message.name {
-            Some(name) => name.clone(),
-            None => String::new()
-        };
-
-        Ok(Self {
-            id,
-            parent,
-            name
-        })
-    }
-}
\ No newline at end of file
+use crate::{common::MumbleResult, mumbleproto::ChannelState};
+
+use std::collections::HashMap;
+
+/// The channel tree of the server, keyed by channel ID and kept up to date
+/// from `ChannelState` and `ChannelRemove` messages. The root channel has
+/// ID 0 and is its own parent.
+#[derive(Debug, Default, Clone)]
+pub struct ChannelList {
+    channels: HashMap<u32, Channel>
+}
+
+impl ChannelList {
+
+    /// Inserts `channel`, replacing any channel with the same ID.
+    pub fn push(&mut self, channel: Channel) -> MumbleResult<()> {
+        self.channels.insert(channel.id, channel);
+
+        Ok(())
+    }
+
+    /// Applies a `ChannelState`, creating the channel if the ID is new. Only
+    /// the fields present in the message change. Returns `None` for a
+    /// message without a channel ID.
+    pub(crate) fn merge(&mut self, state: &ChannelState) -> Option<&Channel> {
+        let id = state.channel_id?;
+
+        if !self.channels.contains_key(&id) {
+            // channels may be announced as linked before they exist
+            let mut channel = Channel::new(id);
+            channel.links = self.channels.values()
+                .filter(|other| other.links.contains(&id))
+                .map(|other| other.id)
+                .collect();
+
+            self.channels.insert(id, channel);
+        }
+
+        let channel = self.channels.get_mut(&id)?;
+        channel.merge(state);
+
+        // links go both ways, but the server only announces one side
+        let touches_links = !state.links.is_empty() || !state.links_add.is_empty() || !state.links_remove.is_empty();
+        if touches_links {
+            let links = channel.links.clone();
+
+            for other in self.channels.values_mut().filter(|other| other.id != id) {
+                let linked = links.contains(&other.id);
+                let linked_back = other.links.contains(&id);
+
+                if linked && !linked_back {
+                    other.links.push(id);
+                } else if !linked && linked_back {
+                    other.links.retain(|&link| link != id);
+                }
+            }
+        }
+
+        self.channels.get(&id)
+    }
+
+    /// Removes the channel and everything below it, as well as any links
+    /// pointing at them.
+    pub(crate) fn remove(&mut self, id: u32) -> Option<Channel> {
+        let removed: Vec<u32> = self.depth_first_from(id).iter().map(|channel| channel.id).collect();
+
+        for channel in self.channels.values_mut() {
+            channel.links.retain(|link| !removed.contains(link));
+        }
+
+        let channel = self.channels.remove(&id);
+        for id in &removed {
+            self.channels.remove(id);
+        }
+
+        channel
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Channel> {
+        self.channels.get(&id)
+    }
+
+    pub fn root(&self) -> Option<&Channel> {
+        self.get(0)
+    }
+
+    /// The direct subchannels of `id`, in the order clients display them:
+    /// by position, then by name.
+    pub fn children(&self, id: u32) -> Vec<&Channel> {
+        let mut children: Vec<&Channel> = self.channels.values()
+            .filter(|channel| channel.parent == id && channel.id != id)
+            .collect();
+
+        children.sort_by(|a, b| a.position.cmp(&b.position).then_with(|| a.name.cmp(&b.name)));
+        children
+    }
+
+    /// The parent of `id`, its parent and so on up to and including the
+    /// root channel.
+    pub fn ancestors(&self, id: u32) -> Vec<&Channel> {
+        let mut ancestors = Vec::new();
+        let mut channel = self.get(id);
+
+        while let Some(current) = channel {
+            if current.id == current.parent {
+                break;
+            }
+
+            channel = self.get(current.parent);
+            // a parent that is already listed means a broken tree
+            match channel {
+                Some(parent) if !ancestors.iter().any(|&ancestor: &&Channel| ancestor.id == parent.id) => ancestors.push(parent),
+                _ => break
+            }
+        }
+
+        ancestors
+    }
+
+    /// The names from the root down to `id`, separated by slashes, e.g.
+    /// `/Lobby/Games`. The root channel itself is `/`.
+    pub fn path(&self, id: u32) -> Option<String> {
+        let channel = self.get(id)?;
+
+        let mut names: Vec<&str> = self.ancestors(id).iter()
+            .filter(|ancestor| ancestor.id != 0)
+            .map(|ancestor| ancestor.name.as_str())
+            .collect();
+        names.reverse();
+
+        if channel.id != 0 {
+            names.push(&channel.name);
+        }
+
+        Some(format!("/{}", names.join("/")))
+    }
+
+    /// Every channel reachable from the root, parents before their
+    /// children and siblings in display order.
+    pub fn depth_first(&self) -> Vec<&Channel> {
+        self.depth_first_from(0)
+    }
+
+    /// `id` followed by everything below it, in the order of `depth_first`.
+    pub fn depth_first_from(&self, id: u32) -> Vec<&Channel> {
+        let mut channels = Vec::new();
+        let mut stack: Vec<&Channel> = self.get(id).into_iter().collect();
+
+        while let Some(channel) = stack.pop() {
+            channels.push(channel);
+
+            let mut children = self.children(channel.id);
+            children.retain(|child| !channels.iter().any(|visited| visited.id == child.id));
+            stack.extend(children.into_iter().rev());
+        }
+
+        channels
+    }
+
+    /// `id` and every channel linked to it, directly or through other
+    /// linked channels, ordered by ID. Someone talking in any of them is
+    /// heard in all of them.
+    pub fn linked_channels(&self, id: u32) -> Vec<&Channel> {
+        let mut reachable: Vec<&Channel> = self.get(id).into_iter().collect();
+        let mut next = 0;
+
+        while let Some(channel) = reachable.get(next).copied() {
+            next += 1;
+
+            for link in &channel.links {
+                if let Some(linked) = self.get(*link) {
+                    if !reachable.iter().any(|known| known.id == linked.id) {
+                        reachable.push(linked);
+                    }
+                }
+            }
+        }
+
+        reachable.sort_by_key(|channel| channel.id);
+        reachable
+    }
+
+    /// Resolves a slash separated path below the root channel, such as
+    /// `Games/CS/Team A`. Leading, trailing and doubled slashes are ignored,
+    /// so `/` is the root channel. When matching case insensitively, a
+    /// sibling with the exact spelling still wins over other spellings.
+    pub fn find_by_path(&self, path: &str, case_sensitive: bool) -> Option<Channel> {
+
+        let mut channel = self.get(0)?;
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let children = self.children(channel.id);
+
+            let exact = children.iter().find(|child| child.name == name);
+            channel = match exact {
+                Some(child) => child,
+                None if case_sensitive => return None,
+                None => children.iter().find(|child| child.name.to_lowercase() == name.to_lowercase())?
+            };
+        }
+
+        Some(channel.clone())
+    }
+
+    /// Names only have to be unique among siblings, so when several
+    /// channels share `name` the one with the lowest ID is returned. Use
+    /// [`find_by_path`](Self::find_by_path) to tell them apart.
+    pub fn find(&self, name: &str) -> Option<Channel> {
+        self.channels.values()
+            .filter(|channel| channel.name == name)
+            .min_by_key(|channel| channel.id)
+            .cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Channel> {
+        self.channels.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub id: u32,
+    pub parent: u32,
+    pub name: String,
+    /// Only sent for short descriptions, longer ones are announced by their
+    /// hash and have to be requested.
+    pub description: Option<String>,
+    pub description_hash: Option<Vec<u8>>,
+    /// Sort key among siblings, lower comes first.
+    pub position: i32,
+    /// Removed by the server once the last user leaves.
+    pub temporary: bool,
+    /// 0 means the server's default limit applies.
+    pub max_users: u32,
+    /// IDs of the channels this one is linked with.
+    pub links: Vec<u32>,
+    /// Entering is limited to some users by an ACL.
+    pub is_enter_restricted: bool,
+    /// Whether we are allowed to enter, as reported by the server.
+    pub can_enter: bool
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            parent: 0,
+            name: String::new(),
+            description: None,
+            description_hash: None,
+            position: 0,
+            temporary: false,
+            max_users: 0,
+            links: Vec::new(),
+            is_enter_restricted: false,
+            can_enter: true
+        }
+    }
+}
+
+impl Channel {
+
+    pub fn new(id: u32) -> Self {
+        Self {
+            id,
+            ..Self::default()
+        }
+    }
+
+    pub fn from_message(message: &ChannelState) -> MumbleResult<Self> {
+
+        let id = message.channel_id.unwrap_or_default();
+
+        let mut channel = Self::new(id);
+        channel.merge(message);
+
+        Ok(channel)
+    }
+
+    pub(crate) fn merge(&mut self, state: &ChannelState) {
+
+        if let Some(parent) = state.parent {
+            self.parent = parent;
+        }
+        if let Some(name) = &state.name {
+            self.name = name.clone();
+        }
+
+        if let Some(description) = &state.description {
+            self.description = Some(description.clone());
+        }
+        if let Some(description_hash) = &state.description_hash {
+            // a new hash without the text means the old text is outdated
+            if state.description.is_none() && self.description_hash.as_ref() != Some(description_hash) {
+                self.description = None;
+            }
+            self.description_hash = Some(description_hash.clone());
+        }
+
+        self.position = state.position.unwrap_or(self.position);
+        self.temporary = state.temporary.unwrap_or(self.temporary);
+        self.max_users = state.max_users.unwrap_or(self.max_users);
+        self.is_enter_restricted = state.is_enter_restricted.unwrap_or(self.is_enter_restricted);
+        self.can_enter = state.can_enter.unwrap_or(self.can_enter);
+
+        // a full list replaces the links, deltas adjust them
+        if !state.links.is_empty() {
+            self.links = state.links.clone();
+        }
+        for link in &state.links_add {
+            if !self.links.contains(link) {
+                self.links.push(*link);
+            }
+        }
+        self.links.retain(|link| !state.links_remove.contains(link));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_state(id: u32, parent: u32, name: &str) -> ChannelState {
+        ChannelState {
+            channel_id: Some(id),
+            parent: Some(parent),
+            name: Some(name.to_owned()),
+            ..ChannelState::default()
+        }
+    }
+
+    fn tree() -> ChannelList {
+        let mut channels = ChannelList::default();
+
+        channels.merge(&channel_state(0, 0, "Root"));
+        channels.merge(&channel_state(1, 0, "Lobby"));
+        channels.merge(&channel_state(2, 1, "Games"));
+        channels.merge(&channel_state(3, 1, "Music"));
+        channels.merge(&ChannelState {
+            position: Some(-1),
+            ..channel_state(4, 0, "AFK")
+        });
+
+        channels
+    }
+
+    #[test]
+    fn test_merge_channel_state() {
+        let mut channels = tree();
+
+        // a rename updates the channel instead of adding a second one
+        channels.merge(&ChannelState {
+            channel_id: Some(2),
+            name: Some("Gaming".to_owned()),
+            max_users: Some(5),
+            ..ChannelState::default()
+        });
+
+        assert_eq!(channels.len(), 5);
+        let gaming = channels.get(2).unwrap();
+        assert_eq!((gaming.name.as_str(), gaming.parent, gaming.max_users), ("Gaming", 1, 5));
+        assert!(gaming.can_enter);
+
+        channels.merge(&ChannelState {
+            channel_id: Some(2),
+            links_add: vec![3],
+            ..ChannelState::default()
+        });
+        assert_eq!(channels.get(2).unwrap().links, vec![3]);
+        assert_eq!(channels.get(3).unwrap().links, vec![2]);
+
+        channels.merge(&ChannelState {
+            channel_id: Some(3),
+            links_remove: vec![2],
+            ..ChannelState::default()
+        });
+        assert!(channels.get(2).unwrap().links.is_empty());
+        assert!(channels.get(3).unwrap().links.is_empty());
+
+        assert!(channels.merge(&ChannelState::default()).is_none());
+    }
+
+    #[test]
+    fn test_remove_channel() {
+        let mut channels = tree();
+        channels.merge(&ChannelState {
+            channel_id: Some(4),
+            links_add: vec![2],
+            ..ChannelState::default()
+        });
+
+        assert_eq!(channels.remove(1).map(|channel| channel.name), Some("Lobby".to_owned()));
+
+        assert_eq!(channels.len(), 2);
+        assert!(channels.get(2).is_none());
+        assert!(channels.get(4).unwrap().links.is_empty());
+        assert!(channels.remove(1).is_none());
+    }
+
+    #[test]
+    fn test_channel_traversal() {
+        let channels = tree();
+
+        let names = |list: Vec<&Channel>| list.iter().map(|channel| channel.name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(names(channels.children(0)), vec!["AFK", "Lobby"]);
+        assert_eq!(names(channels.children(1)), vec!["Games", "Music"]);
+        assert_eq!(names(channels.ancestors(2)), vec!["Lobby", "Root"]);
+        assert!(channels.ancestors(0).is_empty());
+
+        assert_eq!(channels.path(2).as_deref(), Some("/Lobby/Games"));
+        assert_eq!(channels.path(0).as_deref(), Some("/"));
+        assert!(channels.path(9).is_none());
+
+        assert_eq!(names(channels.depth_first()), vec!["Root", "AFK", "Lobby", "Games", "Music"]);
+    }
+
+    #[test]
+    fn test_linked_channels() {
+        let mut channels = tree();
+
+        // Games - Music - AFK, with Lobby on its own
+        channels.merge(&ChannelState { channel_id: Some(2), links_add: vec![3], ..ChannelState::default() });
+        channels.merge(&ChannelState { channel_id: Some(4), links: vec![3], ..ChannelState::default() });
+
+        let ids = |list: Vec<&Channel>| list.iter().map(|channel| channel.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(channels.linked_channels(2)), vec![2, 3, 4]);
+        assert_eq!(ids(channels.linked_channels(4)), vec![2, 3, 4]);
+        assert_eq!(ids(channels.linked_channels(1)), vec![1]);
+        assert!(channels.linked_channels(9).is_empty());
+
+        channels.merge(&ChannelState { channel_id: Some(3), links_remove: vec![4], ..ChannelState::default() });
+        assert_eq!(ids(channels.linked_channels(4)), vec![4]);
+        assert_eq!(channels.get(3).unwrap().links, vec![2]);
+    }
+
+    #[test]
+    fn test_find_by_path() {
+        let mut channels = tree();
+        channels.merge(&channel_state(5, 1, "AFK"));
+        channels.merge(&channel_state(6, 1, "afk"));
+
+        let find = |path, case_sensitive| channels.find_by_path(path, case_sensitive).map(|channel| channel.id);
+
+        // the same name under different parents
+        assert_eq!(find("AFK", true), Some(4));
+        assert_eq!(find("/Lobby/AFK/", true), Some(5));
+        assert_eq!(find("", true), Some(0));
+
+        assert_eq!(find("lobby/games", true), None);
+        assert_eq!(find("lobby/games", false), Some(2));
+        assert_eq!(find("Lobby/afk", false), Some(6));
+        assert_eq!(find("Lobby/Nope", false), None);
+
+        assert_eq!(channels.find("AFK").map(|channel| channel.id), Some(4));
+    }
+}
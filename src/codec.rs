@@ -0,0 +1,197 @@
+use crate::errors::MumbleError;
+use crate::packet::{MessageType, Packet, PacketHeader};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use std::convert::TryFrom;
+use std::io;
+
+/// Size of the type and length prefix in front of every message.
+pub const HEADER_SIZE: usize = 6;
+
+/// Largest payload accepted by default. Murmur refuses anything above 8 MiB
+/// as well.
+pub const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
+/// One message read off the control connection.
+pub enum Frame {
+    Packet(Packet),
+    /// A message type this crate does not know about, e.g. from a newer
+    /// server. The payload has already been consumed, so the stream can
+    /// simply carry on with the next frame.
+    Unknown {
+        message_type: u16,
+        payload: Bytes
+    }
+}
+
+/// Splits the control connection into frames: a big endian `u16` message
+/// type and `u32` payload length, followed by the protobuf payload. Use it
+/// with `tokio_util::codec::Framed` on any transport.
+#[derive(Debug, Clone)]
+pub struct MumbleCodec {
+    max_frame_size: usize
+}
+
+impl MumbleCodec {
+
+    pub fn new() -> Self {
+        Self::with_max_frame_size(MAX_FRAME_SIZE)
+    }
+
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        Self {
+            max_frame_size
+        }
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+}
+
+impl Default for MumbleCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for MumbleCodec {
+    type Item = Frame;
+    type Error = MumbleError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, MumbleError> {
+
+        if src.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let message_type = u16::from_be_bytes([src[0], src[1]]);
+        let size = u32::from_be_bytes([src[2], src[3], src[4], src[5]]) as usize;
+
+        // checked before buffering, so a bogus length cannot make us allocate
+        if size > self.max_frame_size {
+            return Err(MumbleError::FrameTooLarge(size));
+        }
+
+        if src.len() < HEADER_SIZE + size {
+            src.reserve(HEADER_SIZE + size - src.len());
+            return Ok(None);
+        }
+
+        src.advance(HEADER_SIZE);
+        let payload = src.split_to(size).freeze();
+
+        // empty protobuf messages are valid, but a tunnelled voice packet
+        // always carries at least its header byte
+        let message_type = match MessageType::try_from(message_type) {
+            Ok(MessageType::UDPTunnel) if payload.is_empty() => return Err(MumbleError::MalformedPacket),
+            Ok(message_type) => message_type,
+            Err(_) => return Ok(Some(Frame::Unknown { message_type, payload }))
+        };
+
+        let header = PacketHeader::new(message_type, size as u32);
+        Ok(Some(Frame::Packet(Packet::from_data(header, &payload)?)))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, MumbleError> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed in the middle of a message").into())
+        }
+    }
+}
+
+impl Encoder<&Packet> for MumbleCodec {
+    type Error = MumbleError;
+
+    fn encode(&mut self, packet: &Packet, dst: &mut BytesMut) -> Result<(), MumbleError> {
+
+        let payload = packet.payload();
+        if payload.len() > self.max_frame_size {
+            return Err(MumbleError::FrameTooLarge(payload.len()));
+        }
+
+        dst.reserve(HEADER_SIZE + payload.len());
+        dst.put_u16(packet.message_type() as u16);
+        dst.put_u32(payload.len() as u32);
+        dst.put_slice(payload);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mumbleproto::Ping;
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[tokio::test]
+    async fn test_framed_round_trip() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut writer = FramedWrite::new(client, MumbleCodec::new());
+        let mut reader = FramedRead::new(server, MumbleCodec::new());
+
+        let ping = Ping {
+            timestamp: Some(1234),
+            ..Ping::default()
+        };
+        let packet = Packet::from_message(MessageType::Ping, &ping).unwrap();
+
+        // larger than the duplex buffer, so it arrives in pieces
+        let text = Packet::from_message(MessageType::TextMessage, &crate::mumbleproto::TextMessage {
+            message: "x".repeat(200),
+            ..Default::default()
+        }).unwrap();
+
+        tokio::spawn(async move {
+            writer.send(&packet).await.unwrap();
+            writer.send(&text).await.unwrap();
+        });
+
+        match reader.next().await {
+            Some(Ok(Frame::Packet(packet))) => assert_eq!(packet.to_message::<Ping>().unwrap(), ping),
+            _ => panic!("expected a ping")
+        }
+
+        match reader.next().await {
+            Some(Ok(Frame::Packet(packet))) => assert_eq!(packet.payload().len(), 203),
+            _ => panic!("expected a text message")
+        }
+
+        // the writer is gone, which ends the stream without an error
+        assert!(reader.next().await.is_none());
+    }
+
+    #[test]
+    fn test_decode_invalid_frames() {
+        let mut codec = MumbleCodec::with_max_frame_size(16);
+
+        let mut buffer = BytesMut::from(&[0x01, 0x00, 0, 0, 0, 2, 0xaa, 0xbb, 0, 3, 0, 0, 0, 0][..]);
+        match codec.decode(&mut buffer).unwrap() {
+            Some(Frame::Unknown { message_type, payload }) => {
+                assert_eq!(message_type, 256);
+                assert_eq!(&payload[..], &[0xaa, 0xbb]);
+            },
+            _ => panic!("expected an unknown frame")
+        }
+
+        // an empty ping is fine and the stream picks up right after the skipped frame
+        assert!(matches!(codec.decode(&mut buffer).unwrap(), Some(Frame::Packet(_))));
+
+        let mut buffer = BytesMut::from(&[0, 1, 0, 0, 0, 0][..]);
+        assert!(matches!(codec.decode(&mut buffer), Err(MumbleError::MalformedPacket)));
+
+        let mut buffer = BytesMut::from(&[0, 11, 0, 0, 1, 0][..]);
+        assert!(matches!(codec.decode(&mut buffer), Err(MumbleError::FrameTooLarge(256))));
+
+        let mut buffer = BytesMut::from(&[0, 11, 0, 0][..]);
+        assert!(codec.decode_eof(&mut buffer).is_err());
+        assert!(codec.decode_eof(&mut BytesMut::new()).unwrap().is_none());
+    }
+}
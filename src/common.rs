@@ -1,3 +1,2 @@
-
-pub type MumbleResult<T> = Result<T, Box<dyn std::error::Error>>;
-pub type MumbleFuture<T> = Result<T, Box<dyn std::error::Error + Send>>;
\ No newline at end of file
+
+pub type MumbleResult<T> = Result<T, crate::errors::MumbleError>;
\ No newline at end of file
@@ -0,0 +1,949 @@
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::message::ControlMessage;
+use crate::mumbleproto::*;
+use crate::packet::{MessageType, Packet};
+use crate::socket::{SocketReader, SocketWriter};
+use crate::channel::{Channel, ChannelList};
+use crate::deny::DenyMessage;
+use crate::event::Event;
+use crate::mumble::ConnectionConfig;
+use crate::reconnect::ReconnectPolicy;
+use crate::reject::RejectMessage;
+use crate::resolver;
+use crate::stats::ConnectionStats;
+use crate::tls;
+use crate::transport::BoxedTransport;
+use crate::user::UserList;
+use crate::voice::packet::AudioPacket;
+
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tokio::time::{Instant, MissedTickBehavior};
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const MUMBLE_VERSION: u32 = 0x1219;
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many actions may be queued before senders have to wait for the
+/// connection to catch up.
+pub(crate) const COMMAND_CAPACITY: usize = 256;
+
+pub(crate) type Reader = SocketReader<ReadHalf<BoxedTransport>>;
+pub(crate) type Writer = SocketWriter<WriteHalf<BoxedTransport>>;
+
+/// Hands out the streams a test server listens on, in place of dialling the
+/// server when reconnecting.
+#[cfg(test)]
+pub(crate) type OpenStream = Arc<dyn Fn() -> BoxedTransport + Send + Sync>;
+
+pub(crate) enum MumbleAction {
+    MoveChannel {
+        channel: Channel
+    },
+    SetComment {
+        comment: String
+    },
+    SetSelfMute {
+        self_mute: bool
+    },
+    SetSelfDeaf {
+        self_deaf: bool
+    },
+    RegisterVoiceTarget {
+        id: u32,
+        targets: Vec<voice_target::Target>
+    },
+    ListenToChannel {
+        channel_id: u32,
+        listen: bool
+    },
+    SendMessage {
+        message: String,
+        channel_id: Option<u32>
+    },
+    CreateChannel {
+        parent: u32,
+        name: String,
+        temporary: bool
+    },
+    // only the fields that are set change
+    UpdateChannel {
+        channel_state: ChannelState
+    },
+    RemoveChannel {
+        channel_id: u32
+    },
+    #[allow(dead_code)]
+    SendVoice {
+        audio_packet: AudioPacket
+    }
+}
+
+/// Answers whoever waits for an action. Channel administration is answered
+/// with the channel as the server echoed it, everything else with `None`.
+pub(crate) type Reply = oneshot::Sender<MumbleResult<Option<Channel>>>;
+
+/// An action, and whoever waits for the server to confirm it.
+pub(crate) struct Command {
+    pub(crate) action: MumbleAction,
+    pub(crate) reply: Option<Reply>
+}
+
+// the echo that shows an action took effect. Actions the server does not
+// echo, or that changed nothing, are confirmed by the barrier ping
+enum Confirmation {
+    Channel(u32),
+    Comment(String),
+    SelfMute(bool),
+    SelfDeaf(bool),
+    ChannelCreated {
+        parent: u32,
+        name: String
+    },
+    ChannelUpdated(u32),
+    ChannelRemoved(u32),
+    VoiceTarget {
+        id: u32,
+        targets: Vec<voice_target::Target>
+    },
+    Listen {
+        channel_id: u32,
+        listen: bool
+    },
+    Barrier
+}
+
+impl Confirmation {
+    fn matches_channel(&self, channel: &Channel, created: bool) -> bool {
+        match self {
+            Confirmation::ChannelCreated { parent, name } => created && channel.parent == *parent && channel.name == *name,
+            Confirmation::ChannelUpdated(channel_id) => channel.id == *channel_id,
+            _ => false
+        }
+    }
+
+    fn matches_user(&self, user_state: &UserState) -> bool {
+        match self {
+            Confirmation::Channel(channel_id) => user_state.channel_id == Some(*channel_id),
+            // long comments are only announced by their hash
+            Confirmation::Comment(_) => user_state.comment.is_some() || user_state.comment_hash.is_some(),
+            Confirmation::SelfMute(self_mute) => user_state.self_mute == Some(*self_mute),
+            Confirmation::SelfDeaf(self_deaf) => user_state.self_deaf == Some(*self_deaf),
+            _ => false
+        }
+    }
+
+    // our own state only changes once the server agreed to it, so a denied
+    // action is neither used for messages nor restored after a reconnect
+    fn apply(&self, user_info: &mut UserInfo) {
+        match self {
+            Confirmation::Channel(channel_id) => user_info.channel_id = *channel_id,
+            Confirmation::Comment(comment) => user_info.comment = Some(comment.clone()),
+            Confirmation::SelfMute(self_mute) => user_info.self_mute = *self_mute,
+            Confirmation::SelfDeaf(self_deaf) => user_info.self_deaf = *self_deaf,
+            Confirmation::VoiceTarget { id, targets } if targets.is_empty() => {
+                user_info.voice_targets.remove(id);
+            },
+            Confirmation::VoiceTarget { id, targets } => {
+                user_info.voice_targets.insert(*id, targets.clone());
+            },
+            Confirmation::Listen { channel_id, listen } => {
+                user_info.listening_channels.retain(|id| id != channel_id);
+                if *listen {
+                    user_info.listening_channels.push(*channel_id);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+// The server handles messages in order, so the echo of a ping sent right
+// after an action arrives only once the action was applied or denied. A
+// PermissionDenied therefore always belongs to the oldest pending action.
+struct PendingAction {
+    confirmation: Confirmation,
+    barrier: u64,
+    reply: Option<Reply>
+}
+
+impl PendingAction {
+    fn resolve(self, result: MumbleResult<Option<Channel>>) {
+        if let Some(reply) = self.reply {
+            reply.send(result).unwrap_or_default();
+        }
+    }
+}
+
+// state of our own session, restored after reconnecting
+#[derive(Default)]
+pub(crate) struct UserInfo {
+    pub(crate) session_id: u32,
+    pub(crate) name: String,
+    pub(crate) channel_id: u32,
+    pub(crate) comment: Option<String>,
+    pub(crate) self_mute: bool,
+    pub(crate) self_deaf: bool,
+    pub(crate) voice_targets: BTreeMap<u32, Vec<voice_target::Target>>,
+    pub(crate) listening_channels: Vec<u32>
+}
+
+/// The state shared between a connection and the handles that observe it.
+#[derive(Clone)]
+pub(crate) struct Shared {
+    pub(crate) user_info: Arc<Mutex<UserInfo>>,
+    pub(crate) channels: Arc<Mutex<ChannelList>>,
+    pub(crate) users: Arc<Mutex<UserList>>,
+    pub(crate) stats: Arc<Mutex<ConnectionStats>>
+}
+
+impl Shared {
+    pub(crate) fn new(user_info: UserInfo) -> Self {
+        Self {
+            user_info: Arc::new(Mutex::new(user_info)),
+            channels: Arc::new(Mutex::new(ChannelList::default())),
+            users: Arc::new(Mutex::new(UserList::default())),
+            stats: Arc::new(Mutex::new(ConnectionStats::default()))
+        }
+    }
+}
+
+pub(crate) async fn open_stream(config: &ConnectionConfig) -> MumbleResult<BoxedTransport> {
+
+    #[cfg(test)]
+    if let Some(open_stream) = &config.open_stream {
+        return Ok(open_stream());
+    }
+
+    let tcp_connect = async {
+        match &config.proxy {
+            // the proxy resolves the name itself
+            Some(proxy) => Ok((proxy.connect(&config.address).await?, tls::host_from_address(&config.address).to_owned())),
+            None => resolver::connect(config.resolver.as_ref(), &config.address, config.happy_eyeballs_delay).await
+        }
+    };
+
+    let (tcp_stream, host) = tokio::time::timeout(config.tcp_connect_timeout, tcp_connect).await??;
+    let handshake = tls::connect(&config.address, &host, tcp_stream, &config.tls_config);
+
+    tokio::time::timeout(config.handshake_timeout, handshake).await?
+}
+
+pub(crate) fn split_stream(stream: BoxedTransport) -> (Reader, Writer) {
+    let (reader, writer) = tokio::io::split(stream);
+
+    (SocketReader::new(reader), SocketWriter::new(writer))
+}
+
+pub(crate) async fn authenticate(writer: &mut Writer, config: &ConnectionConfig) -> MumbleResult<()> {
+
+    let version = Version {
+        version: Some(MUMBLE_VERSION),
+        os: config.client_name.clone(),
+        os_version: config.client_version.clone(),
+        release: None
+    };
+    writer.write_message(MessageType::Version, &version).await?;
+
+    let authenticate = Authenticate {
+        username: Some(config.username.clone()),
+        password: config.password.clone(),
+        tokens: config.tokens.clone(),
+        opus: Some(config.opus),
+        celt_versions: config.celt_versions.clone()
+    };
+    writer.write_message(MessageType::Authenticate, &authenticate).await?;
+
+    Ok(())
+}
+
+// microseconds since the epoch, echoed back by the server
+fn ping_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+// What the server sends again before ServerSync after a reconnect. It is
+// compared with the state from before the connection was lost, so that
+// only real changes are reported.
+#[derive(Default)]
+struct Resync {
+    channels: ChannelList,
+    users: UserList,
+    // the latest state received for each user
+    user_states: HashMap<u32, UserState>
+}
+
+impl Resync {
+    // ordered so that parents are created before their children and users
+    // are gone before their channels are
+    fn events(&self, channels: &ChannelList, users: &UserList) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for channel in self.channels.depth_first() {
+            match channels.get(channel.id) {
+                None => events.push(Event::ChannelCreated { channel: channel.clone() }),
+                Some(old) if old != channel => events.push(Event::ChannelUpdated { channel: channel.clone() }),
+                Some(_) => {}
+            }
+        }
+
+        let mut sessions: Vec<u32> = self.users.iter().map(|user| user.session).collect();
+        sessions.sort_unstable();
+
+        for session in sessions {
+            let user = match self.users.get(session) {
+                Some(user) => user,
+                None => continue
+            };
+            let state = self.user_states.get(&session).cloned().unwrap_or_default();
+
+            match users.get(session) {
+                None => events.push(Event::UserConnected { session, state }),
+                Some(old) if old.channel_id != user.channel_id => {
+                    events.push(Event::UserMoved { session, actor: None, from: old.channel_id, to: user.channel_id });
+                },
+                Some(old) if old != user => events.push(Event::UserStateChanged { session, state }),
+                Some(_) => {}
+            }
+        }
+
+        let mut gone: Vec<u32> = users.iter()
+            .map(|user| user.session)
+            .filter(|session| self.users.get(*session).is_none())
+            .collect();
+        gone.sort_unstable();
+
+        for session in gone {
+            events.push(Event::UserDisconnected { session, actor: None, reason: None, ban: false });
+        }
+
+        for channel in channels.depth_first().into_iter().rev() {
+            if self.channels.get(channel.id).is_none() {
+                events.push(Event::ChannelRemoved { channel_id: channel.id });
+            }
+        }
+
+        events
+    }
+}
+
+/// Owns the socket and is the only task that touches it. Socket reads,
+/// queued actions, pings and the liveness deadline are all driven from one
+/// `select!` loop, so nothing waits on a lock or a polling interval.
+pub(crate) struct Connection {
+    config: Arc<ConnectionConfig>,
+    reader: Reader,
+    writer: Writer,
+    commands: mpsc::Receiver<Command>,
+    shared: Shared,
+    events: broadcast::Sender<Event>,
+    // actions sent but not yet confirmed or denied, oldest first
+    pending: VecDeque<PendingAction>,
+    // pings are told apart by their timestamp, which therefore never repeats
+    last_timestamp: u64,
+    // answered once the first ServerSync or Reject arrives
+    synchronized: Option<oneshot::Sender<MumbleResult<()>>>,
+    // got through ServerSync at least once, so worth re-establishing
+    established: bool,
+    // reconnect attempts since the session was last synchronized, so that
+    // the backoff keeps growing when the server rejects us
+    attempt: u32,
+    // set while reconnecting, until ServerSync
+    resync: Option<Resync>,
+    // the session is declared dead if the server shows no sign of life by
+    // then. Unset until the first ServerSync, which the client times itself
+    deadline: Option<Instant>
+}
+
+impl Connection {
+
+    pub(crate) fn new(
+        config: Arc<ConnectionConfig>,
+        reader: Reader,
+        writer: Writer,
+        commands: mpsc::Receiver<Command>,
+        shared: Shared,
+        events: broadcast::Sender<Event>,
+        synchronized: oneshot::Sender<MumbleResult<()>>
+    ) -> Self {
+        Self {
+            config,
+            reader,
+            writer,
+            commands,
+            shared,
+            events,
+            pending: VecDeque::new(),
+            last_timestamp: 0,
+            synchronized: Some(synchronized),
+            established: false,
+            attempt: 0,
+            resync: None,
+            deadline: None
+        }
+    }
+
+    /// Runs until the connection is gone for good or every handle to it has
+    /// been dropped.
+    pub(crate) async fn run(mut self) {
+
+        // several pings fit into the liveness timeout, so a single late echo
+        // does not end a healthy session
+        let period = PING_INTERVAL.min(self.config.liveness_timeout / 3).max(Duration::from_millis(1));
+        let mut ping_interval = tokio::time::interval_at(Instant::now() + period, period);
+        ping_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let deadline = self.deadline;
+
+            let alive = tokio::select! {
+                result = self.reader.read_packet() => match result {
+                    Ok(packet) => self.handle_packet(packet).await,
+                    Err(_) => self.connection_lost().await
+                },
+                command = self.commands.recv() => match command {
+                    Some(command) => {
+                        self.handle_command(command).await;
+                        true
+                    },
+                    None => false
+                },
+                _ = ping_interval.tick() => {
+                    self.ping().await.map(|_| ()).unwrap_or_default();
+                    true
+                },
+                // a server that stopped echoing pings is gone, even if the
+                // socket still looks open
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    self.connection_lost().await
+                }
+            };
+
+            if !alive {
+                return;
+            }
+        }
+    }
+
+    // reconnects if the policy allows it, returns whether the connection is
+    // still usable
+    async fn connection_lost(&mut self) -> bool {
+
+        let config = Arc::clone(&self.config);
+        let policy = match &config.reconnect {
+            Some(policy) if self.established => policy,
+            _ => {
+                self.disconnected();
+                return false;
+            }
+        };
+
+        self.deadline = None;
+
+        match reconnect(policy, &config, &self.events, &mut self.attempt).await {
+            Some((reader, writer)) => {
+                self.reader = reader;
+                self.writer = writer;
+
+                // the server sends every channel and user again before
+                // ServerSync, handles keep seeing the old state until then
+                self.resync = Some(Resync::default());
+                // dropping the replies reports the actions as lost
+                self.pending.clear();
+
+                self.deadline = Some(Instant::now() + config.sync_timeout);
+                true
+            },
+            None => {
+                self.disconnected();
+                false
+            }
+        }
+    }
+
+    fn disconnected(&mut self) {
+        if let Some(synchronized) = self.synchronized.take() {
+            synchronized.send(Err(MumbleError::Disconnected)).unwrap_or_default();
+        }
+
+        self.events.send(Event::Disconnected).unwrap_or_default();
+    }
+
+    // reports our latency measurements back to the server, which shows them
+    // in its user information dialog
+    async fn ping(&mut self) -> MumbleResult<u64> {
+
+        self.last_timestamp = ping_timestamp().max(self.last_timestamp + 1);
+        let timestamp = self.last_timestamp;
+
+        let stats = self.shared.stats.lock().await.clone();
+
+        let ping_message = Ping {
+            good: None,
+            lost: None,
+            resync: None,
+            late: None,
+            tcp_packets: Some(stats.tcp_packets),
+            tcp_ping_avg: Some(stats.tcp_ping_avg),
+            tcp_ping_var: Some(stats.tcp_ping_var),
+            udp_packets: None,
+            udp_ping_avg: None,
+            udp_ping_var: None,
+            timestamp: Some(timestamp)
+        };
+
+        self.writer.write_message(MessageType::Ping, &ping_message).await?;
+
+        Ok(timestamp)
+    }
+
+    // the restored state goes through the pending queue like any action, so
+    // a denial of it is not blamed on a queued action
+    async fn restore_session(&mut self) -> MumbleResult<()> {
+
+        let messages = {
+            let user_info = self.shared.user_info.lock().await;
+
+            let channel_id = match user_info.channel_id {
+                0 => None,
+                channel_id => Some(channel_id)
+            };
+
+            let user_state = UserState {
+                session: Some(user_info.session_id),
+                channel_id,
+                comment: user_info.comment.clone(),
+                self_mute: Some(user_info.self_mute),
+                self_deaf: Some(user_info.self_deaf),
+                listening_channel_add: user_info.listening_channels.clone(),
+                ..UserState::default()
+            };
+
+            let mut messages = vec![ControlMessage::UserState(user_state)];
+            for (id, targets) in &user_info.voice_targets {
+                messages.push(ControlMessage::VoiceTarget(VoiceTarget {
+                    id: Some(*id),
+                    targets: targets.clone()
+                }));
+            }
+
+            messages
+        };
+
+        for message in messages {
+            self.send(message).await?;
+            let barrier = self.ping().await?;
+
+            self.pending.push_back(PendingAction { confirmation: Confirmation::Barrier, barrier, reply: None });
+        }
+
+        Ok(())
+    }
+
+    // write errors fail the action, the read side notices the broken
+    // connection and reconnects
+    async fn handle_command(&mut self, command: Command) {
+
+        let Command { action, reply } = command;
+
+        // the lock is released before writing, handles only wait for the
+        // bookkeeping
+        let (message, confirmation) = {
+            let user_info = self.shared.user_info.lock().await;
+
+            match action {
+                MumbleAction::MoveChannel { channel} => {
+                    let user_state = UserState {
+                        session: Some(user_info.session_id),
+                        name: Some(user_info.name.clone()),
+                        channel_id: Some(channel.id),
+                        ..UserState::default()
+                    };
+                    (Some(ControlMessage::UserState(user_state)), Confirmation::Channel(channel.id))
+                },
+                MumbleAction::SetComment { comment} => {
+                    let user_state = UserState {
+                        session: Some(user_info.session_id),
+                        comment: Some(comment.clone()),
+                        name: Some(user_info.name.clone()),
+                        ..UserState::default()
+                    };
+                    (Some(ControlMessage::UserState(user_state)), Confirmation::Comment(comment))
+                },
+                MumbleAction::SetSelfMute { self_mute } => {
+                    let user_state = UserState {
+                        session: Some(user_info.session_id),
+                        self_mute: Some(self_mute),
+                        ..UserState::default()
+                    };
+                    (Some(ControlMessage::UserState(user_state)), Confirmation::SelfMute(self_mute))
+                },
+                MumbleAction::SetSelfDeaf { self_deaf } => {
+                    let user_state = UserState {
+                        session: Some(user_info.session_id),
+                        self_deaf: Some(self_deaf),
+                        ..UserState::default()
+                    };
+                    (Some(ControlMessage::UserState(user_state)), Confirmation::SelfDeaf(self_deaf))
+                },
+                MumbleAction::RegisterVoiceTarget { id, targets } => {
+                    let voice_target = VoiceTarget {
+                        id: Some(id),
+                        targets: targets.clone()
+                    };
+                    (Some(ControlMessage::VoiceTarget(voice_target)), Confirmation::VoiceTarget { id, targets })
+                },
+                MumbleAction::ListenToChannel { channel_id, listen } => {
+                    let mut user_state = UserState {
+                        session: Some(user_info.session_id),
+                        ..UserState::default()
+                    };
+
+                    if listen {
+                        user_state.listening_channel_add = vec![channel_id];
+                    } else {
+                        user_state.listening_channel_remove = vec![channel_id];
+                    }
+
+                    (Some(ControlMessage::UserState(user_state)), Confirmation::Listen { channel_id, listen })
+                },
+                MumbleAction::SendMessage { message, channel_id } => {
+                    let text_message = TextMessage {
+                        session: vec![user_info.session_id],
+                        message,
+                        channel_id: vec![channel_id.unwrap_or(user_info.channel_id)],
+                        ..TextMessage::default()
+                    };
+
+                    (Some(ControlMessage::TextMessage(text_message)), Confirmation::Barrier)
+                },
+                MumbleAction::CreateChannel { parent, name, temporary } => {
+                    let channel_state = ChannelState {
+                        parent: Some(parent),
+                        name: Some(name.clone()),
+                        temporary: Some(temporary),
+                        ..ChannelState::default()
+                    };
+
+                    (Some(ControlMessage::ChannelState(channel_state)), Confirmation::ChannelCreated { parent, name })
+                },
+                MumbleAction::UpdateChannel { channel_state } => {
+                    let channel_id = channel_state.channel_id.unwrap_or_default();
+                    (Some(ControlMessage::ChannelState(channel_state)), Confirmation::ChannelUpdated(channel_id))
+                },
+                MumbleAction::RemoveChannel { channel_id } => {
+                    let channel_remove = ChannelRemove {
+                        channel_id
+                    };
+
+                    (Some(ControlMessage::ChannelRemove(channel_remove)), Confirmation::ChannelRemoved(channel_id))
+                },
+                MumbleAction::SendVoice { audio_packet: _ } => {
+                    // audio_packet.set_session_id(user_info.session_id as u64);
+
+                    // let mut udp_tunnel = UdpTunnel {
+                    //     packet: audio_packet.to_bytes()
+                    // };
+
+                    // let mut writer = writer_ref.lock().await;
+                    // writer.write_message(MessageType::TextMessage, &text_message).await.unwrap();
+                    (None, Confirmation::Barrier)
+                }
+            }
+        };
+
+        let mut pending = PendingAction { confirmation, barrier: 0, reply };
+
+        let message = match message {
+            Some(message) => message,
+            // nothing was sent, so there is nothing to wait for
+            None => return pending.resolve(Ok(None))
+        };
+
+        let barrier = match self.send(message).await {
+            Ok(()) => self.ping().await,
+            Err(e) => Err(e)
+        };
+
+        match barrier {
+            Ok(barrier) => {
+                pending.barrier = barrier;
+                self.pending.push_back(pending);
+            },
+            Err(e) => pending.resolve(Err(e))
+        }
+    }
+
+    // resolves the oldest pending action that `matches` the echo
+    async fn confirm<F: Fn(&Confirmation) -> bool>(&mut self, matches: F, channel: Option<Channel>) {
+        if let Some(index) = self.pending.iter().position(|pending| matches(&pending.confirmation)) {
+            if let Some(pending) = self.pending.remove(index) {
+                self.settle(pending, channel).await;
+            }
+        }
+    }
+
+    async fn settle(&mut self, pending: PendingAction, channel: Option<Channel>) {
+        pending.confirmation.apply(&mut *self.shared.user_info.lock().await);
+        pending.resolve(Ok(channel));
+    }
+
+    async fn send(&mut self, message: ControlMessage) -> MumbleResult<()> {
+        self.writer.write_packet(&message.to_packet()?).await
+    }
+
+    // returns whether the connection is still usable, like connection_lost
+    async fn handle_packet(&mut self, packet: Packet) -> bool {
+
+        // malformed messages are dropped like unknown ones
+        match ControlMessage::try_from(&packet) {
+            Ok(ControlMessage::ChannelState(channel_state)) if self.resync.is_some() => {
+                if let Some(resync) = &mut self.resync {
+                    resync.channels.merge(&channel_state);
+                }
+            },
+            Ok(ControlMessage::ChannelState(channel_state)) => {
+                let (channel, created) = {
+                    let mut channels = self.shared.channels.lock().await;
+                    let created = channel_state.channel_id.and_then(|id| channels.get(id)).is_none();
+
+                    // the event carries the whole channel, not just the delta
+                    match channels.merge(&channel_state) {
+                        Some(channel) => (channel.clone(), created),
+                        None => return true
+                    }
+                };
+
+                self.confirm(|confirmation| confirmation.matches_channel(&channel, created), Some(channel.clone())).await;
+
+                let event = match created {
+                    true => Event::ChannelCreated { channel },
+                    false => Event::ChannelUpdated { channel }
+                };
+                self.events.send(event).unwrap_or_default();
+            },
+            Ok(ControlMessage::ChannelRemove(channel_remove)) if self.resync.is_some() => {
+                if let Some(resync) = &mut self.resync {
+                    resync.channels.remove(channel_remove.channel_id);
+                }
+            },
+            Ok(ControlMessage::ChannelRemove(channel_remove)) => {
+                self.shared.channels.lock().await.remove(channel_remove.channel_id);
+
+                let channel_id = channel_remove.channel_id;
+                self.confirm(|confirmation| matches!(confirmation, Confirmation::ChannelRemoved(id) if *id == channel_id), None).await;
+                self.events.send(Event::ChannelRemoved { channel_id: channel_remove.channel_id }).unwrap_or_default();
+            },
+            Ok(ControlMessage::UserState(user_state)) if self.resync.is_some() => {
+                if let Some(resync) = &mut self.resync {
+                    let session = user_state.session.unwrap_or_default();
+                    resync.users.merge(&user_state);
+                    resync.user_states.insert(session, user_state);
+                }
+            },
+            Ok(ControlMessage::UserState(user_state)) => {
+                let session = user_state.session.unwrap_or_default();
+
+                let event = {
+                    let mut users = self.shared.users.lock().await;
+                    let from = users.get(session).map(|user| user.channel_id);
+                    users.merge(&user_state);
+
+                    match from {
+                        None => Event::UserConnected { session, state: user_state.clone() },
+                        Some(from) => match user_state.channel_id {
+                            Some(to) if to != from => Event::UserMoved { session, actor: user_state.actor, from, to },
+                            _ => Event::UserStateChanged { session, state: user_state.clone() }
+                        }
+                    }
+                };
+
+                // the server may move or mute us, remember it so the
+                // state is restored correctly after a reconnect
+                let own = {
+                    let mut user_info = self.shared.user_info.lock().await;
+                    let own = user_state.session == Some(user_info.session_id);
+
+                    if own {
+                        if let Some(channel_id) = user_state.channel_id {
+                            user_info.channel_id = channel_id;
+                        }
+                        if let Some(self_mute) = user_state.self_mute {
+                            user_info.self_mute = self_mute;
+                        }
+                        if let Some(self_deaf) = user_state.self_deaf {
+                            user_info.self_deaf = self_deaf;
+                        }
+                    }
+                    own
+                };
+
+                if own {
+                    self.confirm(|confirmation| confirmation.matches_user(&user_state), None).await;
+                }
+
+                self.events.send(event).unwrap_or_default();
+            },
+            Ok(ControlMessage::UserRemove(user_remove)) if self.resync.is_some() => {
+                if let Some(resync) = &mut self.resync {
+                    resync.users.remove(user_remove.session);
+                    resync.user_states.remove(&user_remove.session);
+                }
+            },
+            Ok(ControlMessage::UserRemove(user_remove)) => {
+                self.shared.users.lock().await.remove(user_remove.session);
+
+                self.events.send(Event::UserDisconnected {
+                    session: user_remove.session,
+                    actor: user_remove.actor,
+                    reason: user_remove.reason,
+                    ban: user_remove.ban.unwrap_or_default()
+                }).unwrap_or_default();
+            },
+            Ok(ControlMessage::TextMessage(text_message)) => {
+                self.events.send(Event::TextMessage {
+                    actor: text_message.actor,
+                    sessions: text_message.session,
+                    channel_ids: text_message.channel_id,
+                    tree_ids: text_message.tree_id,
+                    message: text_message.message
+                }).unwrap_or_default();
+            },
+            Ok(ControlMessage::PermissionDenied(permission_denied)) => {
+                let deny_message = DenyMessage::from_message(&permission_denied);
+
+                if let Some(pending) = self.pending.pop_front() {
+                    pending.resolve(Err(MumbleError::PermissionDenied(deny_message.clone())));
+                }
+
+                self.events.send(Event::PermissionDenied(deny_message)).unwrap_or_default();
+            },
+            Ok(ControlMessage::Ping(ping)) => {
+                if self.deadline.is_some() && self.resync.is_none() {
+                    self.deadline = Some(Instant::now() + self.config.liveness_timeout);
+                }
+
+                {
+                    let mut stats = self.shared.stats.lock().await;
+                    stats.record_server_counters(&ping);
+
+                    if let Some(timestamp) = ping.timestamp {
+                        let round_trip = Duration::from_micros(ping_timestamp().saturating_sub(timestamp));
+                        stats.record_round_trip(round_trip);
+                        self.events.send(Event::PingResult { round_trip }).unwrap_or_default();
+                    }
+                }
+
+                // everything sent before this ping went through
+                if let Some(index) = self.pending.iter().position(|pending| Some(pending.barrier) == ping.timestamp) {
+                    let confirmed: Vec<PendingAction> = self.pending.drain(..=index).collect();
+                    for pending in confirmed {
+                        self.settle(pending, None).await;
+                    }
+                }
+            },
+            Ok(ControlMessage::ServerSync(server_sync)) => {
+                let reconnected = self.resync.is_some();
+                if let Some(resync) = self.resync.take() {
+                    let events = {
+                        let mut channels = self.shared.channels.lock().await;
+                        let mut users = self.shared.users.lock().await;
+                        let events = resync.events(&channels, &users);
+
+                        *channels = resync.channels;
+                        *users = resync.users;
+                        events
+                    };
+
+                    for event in events {
+                        self.events.send(event).unwrap_or_default();
+                    }
+                }
+
+                self.established = true;
+                self.attempt = 0;
+                self.deadline = Some(Instant::now() + self.config.liveness_timeout);
+
+                let session = {
+                    let mut user_info = self.shared.user_info.lock().await;
+                    if let Some(session_id) = server_sync.session {
+                        user_info.session_id = session_id;
+                    }
+                    user_info.session_id
+                };
+
+                self.events.send(Event::ServerSync {
+                    session,
+                    max_bandwidth: server_sync.max_bandwidth,
+                    welcome_text: server_sync.welcome_text,
+                    permissions: server_sync.permissions
+                }).unwrap_or_default();
+
+                if reconnected {
+                    self.restore_session().await.unwrap_or_default();
+                    self.events.send(Event::Reconnected).unwrap_or_default();
+                }
+
+                if let Some(synchronized) = self.synchronized.take() {
+                    synchronized.send(Ok(())).unwrap_or_default();
+                }
+            },
+            // Final before the first ServerSync. When reconnecting, murmur
+            // usually still holds our old session and answers UsernameInUse
+            // until it times out, so the attempt is simply retried
+            Ok(ControlMessage::Reject(reject)) => {
+                if let Some(synchronized) = self.synchronized.take() {
+                    synchronized.send(Err(MumbleError::Rejected(RejectMessage::from_message(&reject)))).unwrap_or_default();
+                }
+
+                return self.connection_lost().await;
+            },
+            _ => {}
+        }
+
+        true
+    }
+}
+
+// keeps retrying with backoff until a new connection is authenticated, or
+// the policy gives up
+async fn reconnect(
+    policy: &ReconnectPolicy,
+    config: &ConnectionConfig,
+    events: &broadcast::Sender<Event>,
+    attempt: &mut u32
+) -> Option<(Reader, Writer)> {
+
+    loop {
+        *attempt += 1;
+
+        if let Some(max_attempts) = policy.max_attempts {
+            if *attempt > max_attempts {
+                return None;
+            }
+        }
+
+        let delay = policy.delay(*attempt);
+        events.send(Event::Reconnecting { attempt: *attempt, delay }).unwrap_or_default();
+        tokio::time::sleep(delay).await;
+
+        let (reader, mut writer) = match open_stream(config).await {
+            Ok(stream) => split_stream(stream),
+            Err(_) => continue
+        };
+
+        if authenticate(&mut writer, config).await.is_err() {
+            continue;
+        }
+
+        return Some((reader, writer));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::mumbleproto::PermissionDenied;
+
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenyType {
+    Text = 0,
+    Permission = 1,
+    SuperUser = 2,
+    ChannelName = 3,
+    TextTooLong = 4,
+    H9K = 5,
+    TemporaryChannel = 6,
+    MissingCertificate = 7,
+    UserName = 8,
+    ChannelFull = 9,
+    NestingLimit = 10,
+    ChannelCountLimit = 11,
+    ChannelListenerLimit = 12,
+    UserListenerLimit = 13
+}
+
+impl From<i32> for DenyType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => DenyType::Permission,
+            2 => DenyType::SuperUser,
+            3 => DenyType::ChannelName,
+            4 => DenyType::TextTooLong,
+            5 => DenyType::H9K,
+            6 => DenyType::TemporaryChannel,
+            7 => DenyType::MissingCertificate,
+            8 => DenyType::UserName,
+            9 => DenyType::ChannelFull,
+            10 => DenyType::NestingLimit,
+            11 => DenyType::ChannelCountLimit,
+            12 => DenyType::ChannelListenerLimit,
+            13 => DenyType::UserListenerLimit,
+            _ => DenyType::Text
+        }
+    }
+}
+
+/// The server refused to carry out an action.
+#[derive(Debug, Clone)]
+pub struct DenyMessage {
+    pub deny_type: Option<DenyType>,
+    pub reason: Option<String>,
+    pub permission: Option<u32>,
+    pub channel_id: Option<u32>,
+    pub session: Option<u32>,
+    pub name: Option<String>
+}
+
+impl DenyMessage {
+    pub fn from_message(message: &PermissionDenied) -> Self {
+        Self {
+            deny_type: message.r#type.map(DenyType::from),
+            reason: message.reason.clone(),
+            permission: message.permission,
+            channel_id: message.channel_id,
+            session: message.session,
+            name: message.name.clone()
+        }
+    }
+}
+
+impl Display for DenyMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let deny_type = self.deny_type.unwrap_or(DenyType::Text);
+
+        match &self.reason {
+            Some(reason) => write!(f, "Permission denied ({:?}): {}", deny_type, reason),
+            None => write!(f, "Permission denied ({:?})", deny_type)
+        }
+    }
+}
+
+impl Error for DenyMessage {}
@@ -1,25 +1,198 @@
-use std::error::Error;
-use std::fmt::Display;
-
-#[derive(Debug)]
-pub struct MumbleError {
-    message: String
-}
-
-impl MumbleError {
-    pub fn new(message: &str) -> Self {
-        Self {
-            message: message.to_string()
-        }
-    }
-}
-
-unsafe impl Send for MumbleError {}
-
-impl Display for MumbleError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Mumble Error: {}", self.message)
-    }
-}
-
-impl Error for MumbleError {}
\ No newline at end of file
+use std::array::TryFromSliceError;
+use std::error::Error;
+use std::fmt::Display;
+use std::io;
+
+use crate::deny::DenyMessage;
+use crate::reject::RejectMessage;
+use crate::tls::Fingerprint;
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum MumbleError {
+    /// Reading from or writing to the connection failed.
+    Io(io::Error),
+    /// The TLS handshake or TLS configuration failed.
+    Tls(Box<dyn Error + Send + Sync>),
+    /// The server certificate does not match the pinned fingerprint.
+    CertificateMismatch(CertificateMismatchError),
+    /// A certificate, identity or known servers file could not be used.
+    InvalidCertificate(String),
+    /// A control message payload could not be decoded.
+    Decode(prost::DecodeError),
+    /// A control message could not be encoded.
+    Encode(prost::EncodeError),
+    /// The packet header names a message type this client does not know.
+    UnknownMessageType(u16),
+    /// A packet was larger than the protocol allows.
+    FrameTooLarge(usize),
+    /// A packet was truncated or otherwise malformed.
+    MalformedPacket,
+    /// A `mumble://` URL could not be parsed.
+    InvalidUrl(String),
+    /// No channel matches the given name or path.
+    ChannelNotFound(String),
+    /// The channel is restricted and we may not enter it.
+    ChannelEnterDenied(String),
+    /// The channel already holds as many users as it allows.
+    ChannelFull(String),
+    /// The proxy could not establish a tunnel to the server.
+    Proxy(String),
+    /// The server refused the connection during authentication.
+    Rejected(RejectMessage),
+    /// The server refused to carry out an action.
+    PermissionDenied(DenyMessage),
+    /// A connect, handshake, sync or action deadline passed.
+    Timeout,
+    /// The connection to the server is gone.
+    Disconnected
+}
+
+impl Display for MumbleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MumbleError::Io(e) => write!(f, "I/O error: {}", e),
+            MumbleError::Tls(e) => write!(f, "TLS error: {}", e),
+            MumbleError::CertificateMismatch(e) => write!(f, "{}", e),
+            MumbleError::InvalidCertificate(message) => write!(f, "Invalid certificate: {}", message),
+            MumbleError::Decode(e) => write!(f, "Could not decode message: {}", e),
+            MumbleError::Encode(e) => write!(f, "Could not encode message: {}", e),
+            MumbleError::UnknownMessageType(message_type) => write!(f, "Unknown message type {}", message_type),
+            MumbleError::FrameTooLarge(size) => write!(f, "Packet of {} bytes is too large", size),
+            MumbleError::MalformedPacket => write!(f, "Malformed packet"),
+            MumbleError::InvalidUrl(message) => write!(f, "Invalid URL: {}", message),
+            MumbleError::ChannelNotFound(path) => write!(f, "Channel {} not found", path),
+            MumbleError::ChannelEnterDenied(path) => write!(f, "Not allowed to enter channel {}", path),
+            MumbleError::ChannelFull(path) => write!(f, "Channel {} is full", path),
+            MumbleError::Proxy(message) => write!(f, "Proxy error: {}", message),
+            MumbleError::Rejected(e) => write!(f, "{}", e),
+            MumbleError::PermissionDenied(e) => write!(f, "{}", e),
+            MumbleError::Timeout => write!(f, "Timed out"),
+            MumbleError::Disconnected => write!(f, "Disconnected from server")
+        }
+    }
+}
+
+impl Error for MumbleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MumbleError::Io(e) => Some(e),
+            MumbleError::Tls(e) => Some(e.as_ref()),
+            MumbleError::Decode(e) => Some(e),
+            MumbleError::Encode(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for MumbleError {
+    fn from(e: io::Error) -> Self {
+        MumbleError::Io(e)
+    }
+}
+
+#[cfg(feature = "openssl-tls")]
+impl From<openssl::error::ErrorStack> for MumbleError {
+    fn from(e: openssl::error::ErrorStack) -> Self {
+        MumbleError::Tls(Box::new(e))
+    }
+}
+
+#[cfg(feature = "openssl-tls")]
+impl From<openssl::ssl::Error> for MumbleError {
+    fn from(e: openssl::ssl::Error) -> Self {
+        MumbleError::Tls(Box::new(e))
+    }
+}
+
+#[cfg(feature = "rustls-tls")]
+impl From<rustls::Error> for MumbleError {
+    fn from(e: rustls::Error) -> Self {
+        MumbleError::Tls(Box::new(e))
+    }
+}
+
+#[cfg(feature = "rustls-tls")]
+impl From<p12_keystore::error::Error> for MumbleError {
+    fn from(e: p12_keystore::error::Error) -> Self {
+        MumbleError::Tls(Box::new(e))
+    }
+}
+
+#[cfg(feature = "rustls-tls")]
+impl From<rcgen::Error> for MumbleError {
+    fn from(e: rcgen::Error) -> Self {
+        MumbleError::Tls(Box::new(e))
+    }
+}
+
+impl From<prost::DecodeError> for MumbleError {
+    fn from(e: prost::DecodeError) -> Self {
+        MumbleError::Decode(e)
+    }
+}
+
+impl From<prost::EncodeError> for MumbleError {
+    fn from(e: prost::EncodeError) -> Self {
+        MumbleError::Encode(e)
+    }
+}
+
+impl From<TryFromSliceError> for MumbleError {
+    fn from(_: TryFromSliceError) -> Self {
+        MumbleError::MalformedPacket
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for MumbleError {
+    fn from(_: tokio::time::error::Elapsed) -> Self {
+        MumbleError::Timeout
+    }
+}
+
+impl From<RejectMessage> for MumbleError {
+    fn from(e: RejectMessage) -> Self {
+        MumbleError::Rejected(e)
+    }
+}
+
+impl From<DenyMessage> for MumbleError {
+    fn from(e: DenyMessage) -> Self {
+        MumbleError::PermissionDenied(e)
+    }
+}
+
+impl From<CertificateMismatchError> for MumbleError {
+    fn from(e: CertificateMismatchError) -> Self {
+        MumbleError::CertificateMismatch(e)
+    }
+}
+
+/// The server presented a certificate that does not match the fingerprint
+/// pinned for it.
+#[derive(Debug)]
+pub struct CertificateMismatchError {
+    pub server: String,
+    pub known: Fingerprint,
+    pub presented: Fingerprint
+}
+
+impl Display for CertificateMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Certificate for {} changed: expected {}, got {}", self.server, self.known, self.presented)
+    }
+}
+
+impl Error for CertificateMismatchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_thread_safe<T: Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_error_is_thread_safe() {
+        assert_thread_safe::<MumbleError>();
+    }
+}
@@ -0,0 +1,75 @@
+use crate::channel::Channel;
+use crate::deny::DenyMessage;
+use crate::mumbleproto::UserState;
+
+use std::time::Duration;
+
+pub(crate) const EVENT_CAPACITY: usize = 256;
+
+/// Server side activity, published to every receiver returned by
+/// `MumbleClient::subscribe`. Receivers that fall more than a few hundred
+/// events behind miss the oldest ones.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Initial synchronization finished; `session` is our own session ID.
+    ServerSync {
+        session: u32,
+        max_bandwidth: Option<u32>,
+        welcome_text: Option<String>,
+        permissions: Option<u64>
+    },
+    UserConnected {
+        session: u32,
+        state: UserState
+    },
+    UserDisconnected {
+        session: u32,
+        actor: Option<u32>,
+        reason: Option<String>,
+        ban: bool
+    },
+    UserMoved {
+        session: u32,
+        actor: Option<u32>,
+        from: u32,
+        to: u32
+    },
+    /// A user's state changed; `state` only carries the changed fields.
+    UserStateChanged {
+        session: u32,
+        state: UserState
+    },
+    ChannelCreated {
+        channel: Channel
+    },
+    ChannelUpdated {
+        channel: Channel
+    },
+    ChannelRemoved {
+        channel_id: u32
+    },
+    TextMessage {
+        actor: Option<u32>,
+        sessions: Vec<u32>,
+        channel_ids: Vec<u32>,
+        tree_ids: Vec<u32>,
+        message: String
+    },
+    PermissionDenied(DenyMessage),
+    /// The server echoed one of our pings after `round_trip`.
+    PingResult {
+        round_trip: Duration
+    },
+    /// The connection was lost; reconnect attempt `attempt` starts after
+    /// `delay`.
+    Reconnecting {
+        attempt: u32,
+        delay: Duration
+    },
+    /// The session was re-established and its previous state restored.
+    /// Channels and users that changed while disconnected have been
+    /// reported just before, as if the changes had been seen live.
+    Reconnected,
+    /// The connection was lost and will not be re-established.
+    Disconnected
+}
@@ -0,0 +1,126 @@
+use crate::channel::Channel;
+use crate::deny::DenyMessage;
+use crate::event::Event;
+use crate::mumble::ClientHandle;
+use crate::mumbleproto::UserState;
+
+use async_trait::async_trait;
+
+/// Application callbacks driven by `MumbleClient::run_with_handler`. Every
+/// method does nothing by default, so a bot only implements what it needs.
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+
+    /// Called for every event before the more specific callback below.
+    async fn on_event(&self, _client: &ClientHandle, _event: &Event) {}
+
+    async fn on_server_sync(&self, _client: &ClientHandle, _session: u32, _welcome_text: Option<&str>) {}
+
+    async fn on_text_message(&self, _client: &ClientHandle, _actor: Option<u32>, _channel_ids: &[u32], _message: &str) {}
+
+    async fn on_user_connected(&self, _client: &ClientHandle, _session: u32, _state: &UserState) {}
+
+    /// A user entered `channel_id`, either by connecting or by moving.
+    async fn on_user_joined_channel(&self, _client: &ClientHandle, _session: u32, _channel_id: u32) {}
+
+    /// A user moved out of `channel_id`. Disconnects are reported through
+    /// `on_user_left` instead.
+    async fn on_user_left_channel(&self, _client: &ClientHandle, _session: u32, _channel_id: u32) {}
+
+    async fn on_user_state_changed(&self, _client: &ClientHandle, _session: u32, _state: &UserState) {}
+
+    async fn on_user_left(&self, _client: &ClientHandle, _session: u32, _reason: Option<&str>) {}
+
+    async fn on_channel_created(&self, _client: &ClientHandle, _channel: &Channel) {}
+
+    async fn on_channel_updated(&self, _client: &ClientHandle, _channel: &Channel) {}
+
+    async fn on_channel_removed(&self, _client: &ClientHandle, _channel_id: u32) {}
+
+    async fn on_permission_denied(&self, _client: &ClientHandle, _deny: &DenyMessage) {}
+
+    async fn on_reconnected(&self, _client: &ClientHandle) {}
+
+    async fn on_disconnected(&self, _client: &ClientHandle) {}
+}
+
+pub(crate) async fn dispatch<H: EventHandler + ?Sized>(handler: &H, client: &ClientHandle, event: Event) {
+
+    handler.on_event(client, &event).await;
+
+    match event {
+        Event::ServerSync { session, welcome_text, .. } => {
+            handler.on_server_sync(client, session, welcome_text.as_deref()).await;
+        },
+        Event::TextMessage { actor, channel_ids, message, .. } => {
+            handler.on_text_message(client, actor, &channel_ids, &message).await;
+        },
+        Event::UserConnected { session, state } => {
+            handler.on_user_connected(client, session, &state).await;
+            handler.on_user_joined_channel(client, session, state.channel_id.unwrap_or_default()).await;
+        },
+        Event::UserMoved { session, from, to, .. } => {
+            handler.on_user_left_channel(client, session, from).await;
+            handler.on_user_joined_channel(client, session, to).await;
+        },
+        Event::UserStateChanged { session, state } => {
+            handler.on_user_state_changed(client, session, &state).await;
+        },
+        Event::UserDisconnected { session, reason, .. } => {
+            handler.on_user_left(client, session, reason.as_deref()).await;
+        },
+        Event::ChannelCreated { channel } => {
+            handler.on_channel_created(client, &channel).await;
+        },
+        Event::ChannelUpdated { channel } => {
+            handler.on_channel_updated(client, &channel).await;
+        },
+        Event::ChannelRemoved { channel_id } => {
+            handler.on_channel_removed(client, channel_id).await;
+        },
+        Event::PermissionDenied(deny) => {
+            handler.on_permission_denied(client, &deny).await;
+        },
+        Event::Reconnected => {
+            handler.on_reconnected(client).await;
+        },
+        Event::Disconnected => {
+            handler.on_disconnected(client).await;
+        },
+        Event::PingResult { .. } | Event::Reconnecting { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Mutex<Vec<String>>
+    }
+
+    #[async_trait]
+    impl EventHandler for Recorder {
+        async fn on_user_joined_channel(&self, _client: &ClientHandle, session: u32, channel_id: u32) {
+            self.calls.lock().await.push(format!("joined {} {}", session, channel_id));
+        }
+
+        async fn on_user_left_channel(&self, _client: &ClientHandle, session: u32, channel_id: u32) {
+            self.calls.lock().await.push(format!("left {} {}", session, channel_id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_user_moved() {
+        let handler = Recorder::default();
+        let client = ClientHandle::detached();
+
+        dispatch(&handler, &client, Event::UserMoved { session: 3, actor: None, from: 1, to: 2 }).await;
+        dispatch(&handler, &client, Event::Reconnected).await;
+
+        assert_eq!(*handler.calls.lock().await, vec!["left 3 1", "joined 3 2"]);
+    }
+}
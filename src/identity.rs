@@ -0,0 +1,306 @@
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::tls::{backend, Fingerprint};
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+const IDENTITY_EXTENSION: &str = "p12";
+pub(crate) const CERTIFICATE_VALIDITY_DAYS: u32 = 20 * 365;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    Rsa {
+        bits: u32
+    },
+    /// NIST P-256
+    EllipticCurve
+}
+
+impl Default for KeyType {
+    fn default() -> Self {
+        KeyType::Rsa { bits: 2048 }
+    }
+}
+
+/// A client certificate and its private key, presented to the server during
+/// the TLS handshake so that registration and certificate based ACL groups
+/// apply to the session.
+#[derive(Clone)]
+pub struct Identity {
+    // DER encoded certificate and PKCS#8 private key
+    certificate: Vec<u8>,
+    private_key: Vec<u8>,
+    chain: Vec<Vec<u8>>
+}
+
+impl Identity {
+
+    /// Creates an identity from a DER encoded certificate and a DER encoded
+    /// PKCS#8 private key.
+    pub fn from_der(certificate: Vec<u8>, private_key: Vec<u8>) -> Self {
+        Self::from_parts(certificate, private_key, Vec::new())
+    }
+
+    pub(crate) fn from_parts(certificate: Vec<u8>, private_key: Vec<u8>, chain: Vec<Vec<u8>>) -> Self {
+        Self {
+            certificate,
+            private_key,
+            chain
+        }
+    }
+
+    /// Generates a self-signed client certificate the same way the official
+    /// client does on first run.
+    pub fn generate(name: &str, key_type: KeyType) -> MumbleResult<Self> {
+        backend::generate_identity(name, key_type)
+    }
+
+    /// Reads a PEM encoded certificate, optionally followed by its
+    /// intermediates, and a PEM encoded private key.
+    pub fn from_pem(certificate: &[u8], private_key: &[u8]) -> MumbleResult<Self> {
+        backend::identity_from_pem(certificate, private_key)
+    }
+
+    pub fn from_pem_files<P: AsRef<Path>>(certificate_path: P, private_key_path: P) -> MumbleResult<Self> {
+        let certificate = fs::read(certificate_path)?;
+        let private_key = fs::read(private_key_path)?;
+
+        Self::from_pem(&certificate, &private_key)
+    }
+
+    pub fn from_pkcs12(der: &[u8], passphrase: Option<&str>) -> MumbleResult<Self> {
+        backend::identity_from_pkcs12(der, passphrase.unwrap_or(""))
+    }
+
+    pub fn from_pkcs12_file<P: AsRef<Path>>(path: P, passphrase: Option<&str>) -> MumbleResult<Self> {
+        let der = fs::read(path)?;
+        Self::from_pkcs12(&der, passphrase)
+    }
+
+    /// The DER encoded leaf certificate.
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    /// The DER encoded PKCS#8 private key.
+    pub fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    /// DER encoded intermediates sent along with the leaf certificate.
+    pub fn chain(&self) -> &[Vec<u8>] {
+        &self.chain
+    }
+
+    pub fn certificate_pem(&self) -> String {
+        to_pem("CERTIFICATE", &self.certificate)
+    }
+
+    pub fn private_key_pem(&self) -> String {
+        to_pem("PRIVATE KEY", &self.private_key)
+    }
+
+    pub fn fingerprint(&self) -> MumbleResult<Fingerprint> {
+        Fingerprint::from_der(&self.certificate)
+    }
+
+    /// The certificate hash as Mumble displays it, a lowercase hex SHA-1
+    /// digest of the DER encoded certificate.
+    pub fn hash(&self) -> MumbleResult<String> {
+        Ok(self.fingerprint()?.sha1_hex())
+    }
+
+    pub fn to_pkcs12(&self, name: &str, passphrase: Option<&str>) -> MumbleResult<Vec<u8>> {
+        backend::identity_to_pkcs12(self, name, passphrase.unwrap_or(""))
+    }
+}
+
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let encoded = base64::encode(der);
+
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+
+    pem
+}
+
+pub struct StoredIdentity {
+    pub name: String,
+    pub hash: String
+}
+
+/// A directory of PKCS#12 encoded identities, one `<name>.p12` file each.
+#[derive(Clone)]
+pub struct IdentityStore {
+    directory: PathBuf,
+    passphrase: Option<String>
+}
+
+impl IdentityStore {
+
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            passphrase: None
+        }
+    }
+
+    pub fn set_passphrase(&mut self, passphrase: Option<&str>) -> &mut Self {
+        self.passphrase = passphrase.map(|passphrase| passphrase.to_owned());
+        self
+    }
+
+    fn path(&self, name: &str) -> MumbleResult<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(MumbleError::InvalidCertificate(format!("Invalid identity name {:?}", name)));
+        }
+
+        Ok(self.directory.join(format!("{}.{}", name, IDENTITY_EXTENSION)))
+    }
+
+    pub fn load(&self, name: &str) -> MumbleResult<Identity> {
+        Identity::from_pkcs12_file(self.path(name)?, self.passphrase.as_deref())
+    }
+
+    pub fn save(&self, name: &str, identity: &Identity) -> MumbleResult<()> {
+        let path = self.path(name)?;
+        let der = identity.to_pkcs12(name, self.passphrase.as_deref())?;
+
+        fs::create_dir_all(&self.directory)?;
+
+        // without a passphrase the private key is stored in the clear, so
+        // only the owner may read it
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(path)?;
+        // the mode only applies to new files
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(&der)?;
+
+        Ok(())
+    }
+
+    pub fn create(&self, name: &str, key_type: KeyType) -> MumbleResult<Identity> {
+        let identity = Identity::generate(name, key_type)?;
+        self.save(name, &identity)?;
+
+        Ok(identity)
+    }
+
+    /// Loads the identity called `name`, generating and storing a new one if
+    /// it does not exist yet.
+    pub fn load_or_create(&self, name: &str) -> MumbleResult<Identity> {
+        match fs::metadata(self.path(name)?) {
+            Ok(_) => self.load(name),
+            Err(e) if e.kind() == ErrorKind::NotFound => self.create(name, KeyType::default()),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    pub fn remove(&self, name: &str) -> MumbleResult<()> {
+        fs::remove_file(self.path(name)?)?;
+        Ok(())
+    }
+
+    /// Every identity in the directory, by name. Files that cannot be read
+    /// with the store's passphrase are left out.
+    pub fn list(&self) -> MumbleResult<Vec<StoredIdentity>> {
+
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into())
+        };
+
+        let mut identities = Vec::new();
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(IDENTITY_EXTENSION) {
+                continue;
+            }
+
+            let name = match path.file_stem().and_then(|name| name.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue
+            };
+
+            let hash = match self.load(&name).and_then(|identity| identity.hash()) {
+                Ok(hash) => hash,
+                Err(_) => continue
+            };
+            identities.push(StoredIdentity { name, hash });
+        }
+
+        identities.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(identities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_from_pem() {
+        let generated = Identity::generate("mumble-rs", KeyType::EllipticCurve).unwrap();
+
+        let identity = Identity::from_pem(
+            generated.certificate_pem().as_bytes(),
+            generated.private_key_pem().as_bytes()
+        ).unwrap();
+
+        assert_eq!(identity.hash().unwrap(), generated.hash().unwrap());
+    }
+
+    #[test]
+    fn test_identity_from_pkcs12() {
+        let generated = Identity::generate("mumble-rs", KeyType::default()).unwrap();
+
+        let der = generated.to_pkcs12("mumble-rs", Some("secret")).unwrap();
+        let identity = Identity::from_pkcs12(&der, Some("secret")).unwrap();
+        assert_eq!(identity.hash().unwrap(), generated.hash().unwrap());
+        assert_eq!(identity.hash().unwrap().len(), 40);
+
+        assert!(Identity::from_pkcs12(&der, Some("wrong")).is_err());
+    }
+
+    #[test]
+    fn test_identity_store() {
+        let directory = std::env::temp_dir().join(format!("mumble-rs-identities-{}", std::process::id()));
+        let store = IdentityStore::new(&directory);
+
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.load_or_create("../bot").is_err());
+
+        let created = store.load_or_create("bot").unwrap();
+        let loaded = store.load_or_create("bot").unwrap();
+        assert_eq!(created.hash().unwrap(), loaded.hash().unwrap());
+
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(directory.join("bot.p12")).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // a broken file does not hide the others
+        fs::write(directory.join("broken.p12"), b"not a certificate").unwrap();
+
+        let identities = store.list().unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].name, "bot");
+        assert_eq!(identities[0].hash, created.hash().unwrap());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
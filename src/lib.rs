@@ -0,0 +1,41 @@
+extern crate tokio;
+#[cfg(feature = "openssl-tls")]
+extern crate openssl;
+#[cfg(feature = "openssl-tls")]
+extern crate tokio_openssl;
+extern crate prost;
+extern crate bytes;
+
+#[cfg(not(any(feature = "openssl-tls", feature = "rustls-tls")))]
+compile_error!("either the `openssl-tls` or the `rustls-tls` feature must be enabled");
+
+pub mod mumbleproto {
+    include!(concat!(env!("OUT_DIR"), "/mumble.rs"));
+}
+
+pub mod common;
+mod utils;
+pub mod errors;
+pub mod packet;
+pub mod codec;
+pub mod message;
+mod socket;
+pub mod mumble;
+mod connection;
+pub mod builder;
+pub mod ping;
+pub mod stats;
+pub mod channel;
+pub mod user;
+pub mod reject;
+pub mod deny;
+pub mod event;
+pub mod handler;
+pub mod reconnect;
+pub mod voice;
+pub mod identity;
+pub mod tls;
+pub mod transport;
+pub mod proxy;
+pub mod resolver;
+pub mod url;
@@ -0,0 +1,193 @@
+use crate::codec::Frame;
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::mumbleproto::*;
+use crate::packet::{MessageType, Packet, PacketHeader};
+
+use bytes::Bytes;
+use prost::Message;
+
+use std::convert::TryFrom;
+
+macro_rules! control_messages {
+    ($($variant:ident($message:ty)),* $(,)?) => {
+
+        /// Any message of the control protocol, decoded into its protobuf
+        /// type. The message type is implied by the variant, so it can never
+        /// disagree with the payload.
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum ControlMessage {
+            /// A voice packet tunnelled through the control connection. Its
+            /// payload is the raw UDP voice packet, not a protobuf message.
+            UDPTunnel(Bytes),
+            $($variant($message),)*
+            /// A message type this crate does not know about.
+            Unknown {
+                id: u16,
+                bytes: Bytes
+            }
+        }
+
+        impl ControlMessage {
+
+            /// Decodes the payload of a message with type `id`.
+            pub fn decode(id: u16, payload: &[u8]) -> MumbleResult<Self> {
+                let message_type = match MessageType::try_from(id) {
+                    Ok(message_type) => message_type,
+                    Err(_) => return Ok(ControlMessage::Unknown { id, bytes: Bytes::copy_from_slice(payload) })
+                };
+
+                Ok(match message_type {
+                    MessageType::UDPTunnel => ControlMessage::UDPTunnel(Bytes::copy_from_slice(payload)),
+                    $(MessageType::$variant => ControlMessage::$variant(<$message>::decode(payload)?),)*
+                })
+            }
+
+            /// The wire ID of the message type.
+            pub fn id(&self) -> u16 {
+                match self {
+                    ControlMessage::UDPTunnel(_) => MessageType::UDPTunnel as u16,
+                    $(ControlMessage::$variant(_) => MessageType::$variant as u16,)*
+                    ControlMessage::Unknown { id, .. } => *id
+                }
+            }
+
+            /// Encodes the payload, without the frame header.
+            pub fn encode(&self) -> MumbleResult<Vec<u8>> {
+                let mut buffer = Vec::new();
+
+                match self {
+                    ControlMessage::UDPTunnel(bytes) | ControlMessage::Unknown { bytes, .. } => buffer.extend_from_slice(bytes),
+                    $(ControlMessage::$variant(message) => message.encode(&mut buffer)?,)*
+                }
+
+                Ok(buffer)
+            }
+        }
+
+        $(
+            impl From<$message> for ControlMessage {
+                fn from(message: $message) -> Self {
+                    ControlMessage::$variant(message)
+                }
+            }
+        )*
+    };
+}
+
+control_messages! {
+    Version(Version),
+    Authenticate(Authenticate),
+    Ping(Ping),
+    Reject(Reject),
+    ServerSync(ServerSync),
+    ChannelRemove(ChannelRemove),
+    ChannelState(ChannelState),
+    UserRemove(UserRemove),
+    UserState(UserState),
+    BanList(BanList),
+    TextMessage(TextMessage),
+    PermissionDenied(PermissionDenied),
+    ACL(Acl),
+    QueryUsers(QueryUsers),
+    CryptSetup(CryptSetup),
+    ContextActionModify(ContextActionModify),
+    ContextAction(ContextAction),
+    UserList(UserList),
+    VoiceTarget(VoiceTarget),
+    PermissionQuery(PermissionQuery),
+    CodecVersion(CodecVersion),
+    UserStats(UserStats),
+    RequestBlob(RequestBlob),
+    ServerConfig(ServerConfig),
+    SuggestConfig(SuggestConfig)
+}
+
+impl ControlMessage {
+
+    /// The known message type, or `None` for `Unknown`.
+    pub fn message_type(&self) -> Option<MessageType> {
+        MessageType::try_from(self.id()).ok()
+    }
+
+    /// Wraps the message in a `Packet`. Unknown messages cannot be
+    /// represented as one, use `to_bytes` for those.
+    pub fn to_packet(&self) -> MumbleResult<Packet> {
+        let message_type = match self.message_type() {
+            Some(message_type) => message_type,
+            None => return Err(MumbleError::UnknownMessageType(self.id()))
+        };
+
+        let payload = self.encode()?;
+        Packet::from_data(PacketHeader::new(message_type, payload.len() as u32), &payload)
+    }
+
+    /// The complete frame: type, length and payload.
+    pub fn to_bytes(&self) -> MumbleResult<Vec<u8>> {
+        let payload = self.encode()?;
+
+        let mut bytes = Vec::with_capacity(6 + payload.len());
+        bytes.extend_from_slice(&self.id().to_be_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+
+        Ok(bytes)
+    }
+}
+
+impl TryFrom<&Packet> for ControlMessage {
+    type Error = MumbleError;
+
+    fn try_from(packet: &Packet) -> MumbleResult<Self> {
+        ControlMessage::decode(packet.message_type() as u16, packet.payload())
+    }
+}
+
+impl TryFrom<Frame> for ControlMessage {
+    type Error = MumbleError;
+
+    fn try_from(frame: Frame) -> MumbleResult<Self> {
+        match frame {
+            Frame::Packet(packet) => ControlMessage::try_from(&packet),
+            Frame::Unknown { message_type, payload } => Ok(ControlMessage::Unknown { id: message_type, bytes: payload })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_message_round_trip() {
+        let text_message = TextMessage {
+            message: "hello".to_owned(),
+            channel_id: vec![3],
+            ..TextMessage::default()
+        };
+
+        let message = ControlMessage::from(text_message.clone());
+        assert_eq!(message.id(), 11);
+
+        let packet = message.to_packet().unwrap();
+        assert_eq!(packet.to_bytes(), message.to_bytes().unwrap());
+        assert_eq!(ControlMessage::try_from(&packet).unwrap(), ControlMessage::TextMessage(text_message));
+
+        let tunnel = ControlMessage::decode(1, &[0x80, 1, 2]).unwrap();
+        assert_eq!(tunnel, ControlMessage::UDPTunnel(Bytes::from_static(&[0x80, 1, 2])));
+        assert_eq!(tunnel.encode().unwrap(), vec![0x80, 1, 2]);
+
+        // a garbled payload is an error rather than a silently empty message
+        assert!(ControlMessage::decode(7, &[0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_unknown_control_message() {
+        let message = ControlMessage::decode(300, &[1, 2, 3]).unwrap();
+
+        assert_eq!(message, ControlMessage::Unknown { id: 300, bytes: Bytes::from_static(&[1, 2, 3]) });
+        assert!(message.message_type().is_none());
+        assert!(message.to_packet().is_err());
+        assert_eq!(message.to_bytes().unwrap(), vec![1, 44, 0, 0, 0, 3, 1, 2, 3]);
+    }
+}
@@ -1,493 +1,1247 @@
-use crate::common::MumbleResult;
-use crate::mumbleproto::*;
-use crate::packet::{MessageType, Packet};
-use crate::socket::{SocketReader, SocketWriter};
-use crate::channel::{Channel, ChannelList};
-use crate::voice::packet::{AudioPacket, UdpPacket};
-
-use tokio::{net::TcpStream, task::JoinHandle};
-use tokio::sync::{mpsc, mpsc::{Sender, Receiver}, Mutex};
-use tokio::io::{ReadHalf, WriteHalf};
-use openssl::ssl::{SslMethod, SslVerifyMode, SslConnector};
-use tokio_openssl::SslStream;
-
-use std::{io::Read, pin::Pin};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
-use std::sync::Arc;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
-use std::fs::File;
-
-const MUMBLE_VERSION: u32 = 0x1219;
-
-enum MumbleAction {
-    Ping,
-    MoveChannel {
-        channel: Channel
-    },
-    SetComment {
-        comment: String
-    },
-    SendMessage {
-        message: String,
-        channel_id: Option<u32>,
-        user_id: Option<u32>
-    },
-    SendVoice {
-        audio_packet: AudioPacket
-    }
-}
-
-enum MessageQueue {
-    Action { 
-        action: MumbleAction 
-    },
-    PacketRecieved {
-        packet: Packet
-    }
-}
-
-#[derive(Default)]
-struct UserInfo {
-    session_id: u32,
-    name: String,
-    channel_id: u32
-}
-
-pub struct MumbleClient {
-    client_name: Option<String>,
-    client_version: Option<String>,
-    username: String,
-    password: Option<String>,
-    reader: Arc<Mutex<SocketReader<ReadHalf<SslStream<TcpStream>>>>>,
-    writer: Arc<Mutex<SocketWriter<WriteHalf<SslStream<TcpStream>>>>>,
-    threads: Vec<JoinHandle<()>>,
-    running: Arc<AtomicBool>,
-    tx_channel: Arc<Mutex<Sender<MessageQueue>>>,
-    rx_channel: Arc<Mutex<Receiver<MessageQueue>>>,
-    user_info: Arc<Mutex<UserInfo>>,
-    connected: Arc<AtomicBool>,
-    channels: Arc<Mutex<ChannelList>>
-}
-
-impl MumbleClient {
-
-    pub async fn new(ip_address: &str) -> MumbleResult<Self> {
-
-        let mut connector = SslConnector::builder(SslMethod::tls())?;
-        connector.set_verify(SslVerifyMode::NONE);
-        // connector.set_ca_file("tests/cert.pem")?;
-        let ssl = connector.build()
-            .configure()?
-            .into_ssl("localhost")?;
-
-        let tcp_stream = TcpStream::connect(ip_address).await?;
-        let mut stream = SslStream::new(ssl, tcp_stream)?;
-
-        Pin::new(&mut stream).connect().await?;
-
-        let (reader, writer) = tokio::io::split(stream);
-
-        let (tx, rx) = mpsc::channel::<MessageQueue>(3);
-        let tx = Arc::new(Mutex::new(tx));
-        let rx = Arc::new(Mutex::new(rx));
-
-        Ok(Self {
-            client_name: None,
-            client_version: None,
-            username: String::new(),
-            password: None,
-            reader: Arc::new(Mutex::new(SocketReader::new(reader))),
-            writer: Arc::new(Mutex::new(SocketWriter::new(writer))),
-            threads: Vec::new(),
-            running: Arc::new(AtomicBool::new(false)),
-            rx_channel: rx,
-            tx_channel: tx,
-            user_info: Arc::new(Mutex::new(UserInfo::default())),
-            connected: Arc::new(AtomicBool::new(false)),
-            channels: Arc::new(Mutex::new(ChannelList::default()))
-        })
-    }
-
-    pub async fn set_username(&mut self, username: &str) -> &mut Self {
-        self.username = username.to_owned();
-        let user_info = Arc::clone(&self.user_info);
-        let mut user_info = user_info.lock().await;
-        user_info.name = username.to_owned();
-        self
-    }
-
-    pub fn set_password(&mut self, password: Option<&str>) -> &mut Self {
-        self.password = match password {
-            Some(password) => Some(password.to_owned()),
-            None => None
-        };
-
-        self
-    }
-
-    pub fn set_client_info(&mut self, client_name: Option<&str>, client_version: Option<&str>) -> &mut Self {
-
-        self.client_name = match client_name {
-            Some(client_name) => Some(client_name.to_owned()),
-            None => None
-        };
-
-        self.client_version = match client_version {
-            Some(client_version) => Some(client_version.to_owned()),
-            None => None
-        };
-
-        self
-    }
-
-    pub async fn authenticate(
-        &mut self,
-        tokens: Option<Vec<String>>,
-        opus: bool
-    ) -> MumbleResult<&mut Self> {
-
-        let version = Version {
-            version: Some(MUMBLE_VERSION),
-            os: self.client_name.clone(),
-            os_version: self.client_version.clone(),
-            release: None
-        };
-        let writer = Arc::clone(&self.writer);
-        let mut writer = writer.lock().await;
-        writer.write_message(MessageType::Version, &version).await?;
-
-        let token = match tokens {
-            Some(result) => result,
-            None => Vec::new()
-        };
-
-        let authenticate = Authenticate {
-            username: Some(self.username.clone()),
-            password: self.password.clone(),
-            tokens: token,
-            opus: Some(opus),
-            celt_versions: Vec::new()
-        };
-        writer.write_message(MessageType::Authenticate, &authenticate).await?;
-
-        Ok(self)
-    }
-
-    async fn ping(writer: &mut SocketWriter<WriteHalf<SslStream<TcpStream>>>) -> MumbleResult<Instant> {
-
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_millis() as u64;
-
-        let mut ping_message = Ping::default();
-        ping_message.timestamp = Some(timestamp);
-
-        let ping_message = Ping {
-            good: None,
-            lost: None,
-            resync: None,
-            late: None,
-            tcp_packets: None,
-            tcp_ping_avg: None,
-            tcp_ping_var: None,
-            udp_packets: None,
-            udp_ping_avg: None,
-            udp_ping_var: None,
-            timestamp: Some(timestamp)
-        };
-
-        writer.write_message(MessageType::Ping, &ping_message).await?;
-
-        println!("pinging!");
-
-        Ok(Instant::now())
-    }
-
-    pub async fn listen(
-        &mut self,
-    ) -> MumbleResult<()> {
-
-        // todo: move rx / tx to message handler
-
-        let t1tx = self.tx_channel.clone();
-        let t2tx = self.tx_channel.clone();
-        let t3rx = self.rx_channel.clone();
-
-        self.running.store(true, Ordering::Relaxed);
-
-        let t1_running = Arc::clone(&self.running);
-
-        let t1 = tokio::spawn(async move {
-
-            let mut last_ping_time = Instant::now();
-            let tx = t1tx.clone();
-
-            while t1_running.load(Ordering::Relaxed) {
-                if last_ping_time.elapsed().as_secs() >= 10 {
-
-                    let tx = tx.lock().await;
-                    tx.send(MessageQueue::Action { action: MumbleAction::Ping }).await.unwrap_or_default();
-                    last_ping_time = Instant::now();
-                }
-
-                tokio::time::sleep(Duration::from_secs(1)).await;
-            }
-        });
-
-        let reader_ref = Arc::clone(&self.reader);
-
-        let t2_running = Arc::clone(&self.running);
-
-        let t2 = tokio::spawn(async move {
-            let tx = t2tx.clone();
-            let reader_ref = reader_ref;
-
-            while t2_running.load(Ordering::Relaxed) {
-                let mut reader = reader_ref.lock().await;
-
-                match reader.read_packet().await {
-                    Ok(packet) => {
-                        let tx = tx.lock().await;
-                        tx.send(MessageQueue::PacketRecieved { packet }).await.unwrap_or_default();
-                    },
-                    _ => {}
-                };
-
-                drop(reader);
-
-                tokio::time::sleep(Duration::from_millis(1)).await;
-            }
-        });
-
-        let writer_ref = Arc::clone(&self.writer);
-        let t3_running = Arc::clone(&self.running);
-        let user_info = Arc::clone(&self.user_info);
-
-        let connected = Arc::clone(&self.connected);
-        let channels = Arc::clone(&self.channels);
-
-        let t3 = tokio::spawn(async move {
-            let rx = t3rx.clone();
-            let writer_ref = writer_ref.clone();
-            let user_info = user_info;
-            let connected = connected;
-
-            while t3_running.load(Ordering::Relaxed) {
-
-                let message = {
-                    let mut rx = rx.lock().await;
-
-                    match rx.recv().await {
-                        Some(message) => message,
-                        None => continue
-                    }
-                };
-
-                match message {
-                    MessageQueue::Action { action} => { 
-
-                        match action {
-                            MumbleAction::Ping => {
-                                let mut writer = writer_ref.lock().await;
-                                Self::ping(&mut writer).await.unwrap(); 
-                            },
-                            MumbleAction::MoveChannel { channel} => {
-                                let mut user_info = user_info.lock().await;
-
-                                user_info.channel_id = channel.id;
-
-                                let mut user_state = UserState::default();
-                                user_state.session = Some(user_info.session_id);
-                                user_state.name = Some(user_info.name.clone());
-                                user_state.channel_id = Some(channel.id);
-                                let mut writer = writer_ref.lock().await;
-                                writer.write_message(MessageType::UserState, &user_state).await.unwrap();
-                            },
-                            MumbleAction::SetComment { comment} => {
-                                let user_info = user_info.lock().await;
-
-                                let mut user_state = UserState::default();
-                                user_state.session = Some(user_info.session_id);
-                                user_state.comment = Some(comment);
-                                user_state.name = Some(user_info.name.clone());
-                                let mut writer = writer_ref.lock().await;
-                                writer.write_message(MessageType::UserState, &user_state).await.unwrap();
-                            },
-                            MumbleAction::SendMessage { message, channel_id, user_id} => {
-                                let user_info = user_info.lock().await;
-
-                                let mut text_message = TextMessage::default();
-                                text_message.session = vec![user_info.session_id];
-                                text_message.message = message;
-                                text_message.channel_id = vec![user_info.channel_id];
-
-                                if let Some(channel_id) = channel_id {
-                                    text_message.channel_id = vec![channel_id];
-                                }
-
-                                let mut writer = writer_ref.lock().await;
-                                writer.write_message(MessageType::TextMessage, &text_message).await.unwrap();
-                            },
-                            MumbleAction::SendVoice { audio_packet} => {
-                                let user_info = user_info.lock().await;
-
-                                // audio_packet.set_session_id(user_info.session_id as u64);
-
-                                // let mut udp_tunnel = UdpTunnel {
-                                //     packet: audio_packet.to_bytes()
-                                // };
-
-                                // let mut writer = writer_ref.lock().await;
-                                // writer.write_message(MessageType::TextMessage, &text_message).await.unwrap();
-                            }
-                        }
-                    },
-                    MessageQueue::PacketRecieved { packet} => {
-                        match packet.message_type() {
-                            MessageType::ChannelState => {
-                                let channel_state: ChannelState = packet.to_message().unwrap();
-                                let channel = Channel::from_message(&channel_state).unwrap();
-                                let mut channels = channels.lock().await;
-                                channels.push(channel).unwrap();
-
-                                if let Some(name) = channel_state.name {
-                                    println!("Name: {}", name);
-                                }
-                            },
-                            MessageType::UserState => {
-                                let user_state: UserState = packet.to_message().unwrap();
-                                // println!("{:?}", user_state);
-                                if let Some(name) = user_state.name {
-                                    println!("Name: {}", name);
-                                }
-                            },
-                            MessageType::Ping => {
-                                let ping: Ping = packet.to_message().unwrap();
-                                println!("{:?}", ping);
-                            },
-                            MessageType::ServerSync => {
-                                if !connected.load(Ordering::Relaxed) {
-                                    connected.store(true, Ordering::Relaxed);
-                                }
-                                let mut user_info = user_info.lock().await;
-                                let server_sync: ServerSync = packet.to_message().unwrap();
-                                if let Some(session_id) = server_sync.session {
-                                    user_info.session_id = session_id;
-                                }
-                            },
-                            _ => {}
-                        }
-                    }
-                }
-
-                tokio::time::sleep(Duration::from_millis(10)).await;
-            }
-        });
-
-        self.threads.push(t1);
-        self.threads.push(t2);
-        self.threads.push(t3);
-
-        self.wait_for_connection().await?;
-
-        Ok(())
-    }
-
-    async fn wait_for_connection(&mut self) -> MumbleResult<()> {
-        let connected = Arc::clone(&self.connected);
-        while !connected.load(Ordering::Relaxed) {}
-
-        Ok(())
-    }
-
-    pub async fn set_comment(&mut self, comment: &str) -> MumbleResult<()> {
-        let tx = self.tx_channel.clone();
-        let message = MessageQueue::Action {
-            action: MumbleAction::SetComment {
-                comment: comment.to_owned()
-            }
-        };
-        let tx = tx.lock().await;
-        tx.send(message).await.unwrap_or_default();
-
-        Ok(())
-    }
-
-    pub async fn get_channels(&self) -> ChannelList {
-        let channels = self.channels.clone();
-        let channels = channels.lock().await;
-        channels.clone()
-    }
-
-    pub async fn join_channel(&mut self, channel: Channel) -> MumbleResult<()> {
-        let tx = self.tx_channel.clone();
-        let message = MessageQueue::Action {
-            action: MumbleAction::MoveChannel {
-                channel
-            }
-        };
-        let tx = tx.lock().await;
-        tx.send(message).await.unwrap_or_default();
-
-        Ok(())
-    }
-
-    pub async fn send_message(&mut self, message: &str) -> MumbleResult<()> {
-        let tx = self.tx_channel.clone();
-        let message = MessageQueue::Action {
-            action: MumbleAction::SendMessage {
-                message: message.to_owned(),
-                channel_id: None,
-                user_id: None
-            }
-        };
-        let tx = tx.lock().await;
-        tx.send(message).await.unwrap_or_default();
-
-        Ok(())
-    }
-
-    pub async fn send_image(&mut self, file_path: &str) -> MumbleResult<()> {
-
-        let mut contents = File::open(file_path)?;
-        let mut buffer: Vec<u8> = Vec::new();
-        contents.read_to_end(&mut buffer)?;
-        let contents = base64::encode(&buffer);
-
-        // TODO:  send other image types
-
-        let mut data = String::new();
-        data.push_str("<img src=\"data:image/JPEG;base64,");
-        data.push_str(&contents);
-        data.push_str("\"/>");
-
-        self.send_message(&data).await?;
-
-        Ok(())
-    }
-
-    pub async fn shutdown(&mut self) -> MumbleResult<()> {
-        self.running.store(false, Ordering::Relaxed);
-
-        for thread in &self.threads {
-            thread.abort();
-        }
-
-        self.threads.clear();
-
-        Ok(())
-    }
-}
-
-
-impl Drop for MumbleClient {
-    fn drop(&mut self) {
-
-        self.running.store(false, Ordering::Relaxed);
-
-        for thread in &self.threads {
-            thread.abort();
-        }
-    }
-}
\ No newline at end of file
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::mumbleproto::*;
+use crate::channel::{Channel, ChannelList};
+use crate::builder::MumbleClientBuilder;
+use crate::connection::{self, Command, Connection, MumbleAction, Shared, UserInfo, COMMAND_CAPACITY};
+use crate::event::{Event, EVENT_CAPACITY};
+use crate::handler::{self, EventHandler};
+use crate::identity::Identity;
+use crate::reconnect::ReconnectPolicy;
+use crate::proxy::Proxy;
+use crate::stats::ConnectionStats;
+use crate::resolver::Resolver;
+use crate::tls::TlsConfig;
+use crate::transport::Transport;
+use crate::user::{User, UserList};
+use crate::url::MumbleUrl;
+
+use tokio::task::JoinHandle;
+use tokio::sync::{broadcast, mpsc, mpsc::Sender, oneshot};
+
+use std::io::Read;
+use std::time::Duration;
+use std::sync::Arc;
+use std::fs::File;
+
+pub(crate) const DEFAULT_TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const DEFAULT_SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+// murmur drops clients after 30 seconds without a message
+pub(crate) const DEFAULT_LIVENESS_TIMEOUT: Duration = Duration::from_secs(30);
+pub(crate) const DEFAULT_ACTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything needed to open and authenticate a connection, kept for the
+/// lifetime of the client so that it can reconnect.
+#[derive(Clone)]
+pub(crate) struct ConnectionConfig {
+    pub(crate) address: String,
+    pub(crate) tls_config: TlsConfig,
+    pub(crate) client_name: Option<String>,
+    pub(crate) client_version: Option<String>,
+    pub(crate) username: String,
+    pub(crate) password: Option<String>,
+    pub(crate) tokens: Vec<String>,
+    pub(crate) opus: bool,
+    pub(crate) celt_versions: Vec<i32>,
+    pub(crate) reconnect: Option<ReconnectPolicy>,
+    pub(crate) proxy: Option<Proxy>,
+    pub(crate) force_tcp_voice: bool,
+    pub(crate) resolver: Arc<dyn Resolver>,
+    pub(crate) happy_eyeballs_delay: Duration,
+    pub(crate) tcp_connect_timeout: Duration,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) sync_timeout: Duration,
+    pub(crate) liveness_timeout: Duration,
+    pub(crate) action_timeout: Duration,
+    #[cfg(test)]
+    pub(crate) open_stream: Option<connection::OpenStream>
+}
+
+
+/// Cheap, cloneable handle for issuing actions to a running client, handed to
+/// every [`EventHandler`] callback.
+#[derive(Clone)]
+pub struct ClientHandle {
+    commands: Sender<Command>,
+    shared: Shared,
+    confirm: bool,
+    action_timeout: Duration
+}
+
+impl ClientHandle {
+
+    /// Our own session ID, valid once the server has synchronized.
+    pub async fn session_id(&self) -> u32 {
+        self.shared.user_info.lock().await.session_id
+    }
+
+    pub async fn get_channels(&self) -> ChannelList {
+        self.shared.channels.lock().await.clone()
+    }
+
+    /// A snapshot of every connected user, ourselves included.
+    pub async fn get_users(&self) -> UserList {
+        self.shared.users.lock().await.clone()
+    }
+
+    pub async fn get_user(&self, session: u32) -> Option<User> {
+        self.shared.users.lock().await.get(session).cloned()
+    }
+
+    /// Latency and voice packet statistics gathered from pings so far.
+    pub async fn stats(&self) -> ConnectionStats {
+        self.shared.stats.lock().await.clone()
+    }
+
+    pub async fn set_comment(&self, comment: &str) -> MumbleResult<()> {
+        self.send_action(MumbleAction::SetComment { comment: comment.to_owned() }).await
+    }
+
+    pub async fn set_self_mute(&self, self_mute: bool) -> MumbleResult<()> {
+        self.send_action(MumbleAction::SetSelfMute { self_mute }).await
+    }
+
+    pub async fn set_self_deaf(&self, self_deaf: bool) -> MumbleResult<()> {
+        self.send_action(MumbleAction::SetSelfDeaf { self_deaf }).await
+    }
+
+    /// Registers voice target `id` (1-30) for whispering and shouting. An
+    /// empty target list unregisters it.
+    pub async fn register_voice_target(&self, id: u32, targets: Vec<voice_target::Target>) -> MumbleResult<()> {
+        self.send_action(MumbleAction::RegisterVoiceTarget { id, targets }).await
+    }
+
+    pub async fn listen_to_channel(&self, channel: &Channel) -> MumbleResult<()> {
+        self.send_action(MumbleAction::ListenToChannel { channel_id: channel.id, listen: true }).await
+    }
+
+    pub async fn stop_listening_to_channel(&self, channel: &Channel) -> MumbleResult<()> {
+        self.send_action(MumbleAction::ListenToChannel { channel_id: channel.id, listen: false }).await
+    }
+
+    /// Moves us into `channel`. Fails without asking the server when the
+    /// channel is gone, restricted for us or full.
+    pub async fn join_channel(&self, channel: Channel) -> MumbleResult<()> {
+        let channel = self.check_enter(channel.id).await?;
+        self.send_action(MumbleAction::MoveChannel { channel }).await
+    }
+
+    /// Moves us into the channel at `path` below the root, e.g.
+    /// `Games/CS/Team A`. See [`ChannelList::find_by_path`].
+    pub async fn join_channel_path(&self, path: &str, case_sensitive: bool) -> MumbleResult<()> {
+        let channel = self.shared.channels.lock().await
+            .find_by_path(path, case_sensitive)
+            .ok_or_else(|| MumbleError::ChannelNotFound(path.to_owned()))?;
+
+        self.join_channel(channel).await
+    }
+
+    /// Creates a channel below `parent`. Temporary channels are removed by
+    /// the server once they are empty, and the server moves us into them.
+    /// Fails with [`MumbleError::ChannelNotFound`] naming the parent's ID
+    /// when the server ignores the request, which it does for a parent that
+    /// no longer exists.
+    pub async fn create_channel(&self, parent: &Channel, name: &str, temporary: bool) -> MumbleResult<Channel> {
+        let action = MumbleAction::CreateChannel {
+            parent: parent.id,
+            name: name.to_owned(),
+            temporary
+        };
+
+        // no echo and no denial means the server ignored the request
+        self.request(action).await?
+            .ok_or_else(|| MumbleError::ChannelNotFound(parent.id.to_string()))
+    }
+
+    pub async fn rename_channel(&self, channel: &Channel, name: &str) -> MumbleResult<Channel> {
+        self.update_channel(ChannelState {
+            channel_id: Some(channel.id),
+            name: Some(name.to_owned()),
+            ..ChannelState::default()
+        }).await
+    }
+
+    pub async fn set_channel_description(&self, channel: &Channel, description: &str) -> MumbleResult<Channel> {
+        self.update_channel(ChannelState {
+            channel_id: Some(channel.id),
+            description: Some(description.to_owned()),
+            ..ChannelState::default()
+        }).await
+    }
+
+    pub async fn set_channel_position(&self, channel: &Channel, position: i32) -> MumbleResult<Channel> {
+        self.update_channel(ChannelState {
+            channel_id: Some(channel.id),
+            position: Some(position),
+            ..ChannelState::default()
+        }).await
+    }
+
+    /// Limits how many users may be in the channel, 0 lifts the limit.
+    pub async fn set_channel_max_users(&self, channel: &Channel, max_users: u32) -> MumbleResult<Channel> {
+        self.update_channel(ChannelState {
+            channel_id: Some(channel.id),
+            max_users: Some(max_users),
+            ..ChannelState::default()
+        }).await
+    }
+
+    /// Moves the channel, with everything below it, under `parent`.
+    pub async fn move_channel(&self, channel: &Channel, parent: &Channel) -> MumbleResult<Channel> {
+        self.update_channel(ChannelState {
+            channel_id: Some(channel.id),
+            parent: Some(parent.id),
+            ..ChannelState::default()
+        }).await
+    }
+
+    /// Links two channels, so that users in either hear each other. Needs
+    /// the link permission in both.
+    pub async fn link_channels(&self, channel: &Channel, other: &Channel) -> MumbleResult<Channel> {
+        self.update_channel(ChannelState {
+            channel_id: Some(channel.id),
+            links_add: vec![other.id],
+            ..ChannelState::default()
+        }).await
+    }
+
+    pub async fn unlink_channels(&self, channel: &Channel, other: &Channel) -> MumbleResult<Channel> {
+        self.update_channel(ChannelState {
+            channel_id: Some(channel.id),
+            links_remove: vec![other.id],
+            ..ChannelState::default()
+        }).await
+    }
+
+    /// Deletes the channel and all of its subchannels.
+    pub async fn remove_channel(&self, channel: &Channel) -> MumbleResult<()> {
+        self.request(MumbleAction::RemoveChannel { channel_id: channel.id }).await.map(drop)
+    }
+
+    async fn update_channel(&self, channel_state: ChannelState) -> MumbleResult<Channel> {
+        let channel_id = channel_state.channel_id.unwrap_or_default();
+
+        match self.request(MumbleAction::UpdateChannel { channel_state }).await? {
+            Some(channel) => Ok(channel),
+            // the server does not echo an update that changed nothing
+            None => self.shared.channels.lock().await.get(channel_id).cloned()
+                .ok_or_else(|| MumbleError::ChannelNotFound(channel_id.to_string()))
+        }
+    }
+
+    /// Sends `message` to the channel we are currently in.
+    pub async fn send_message(&self, message: &str) -> MumbleResult<()> {
+        self.send_action(MumbleAction::SendMessage {
+            message: message.to_owned(),
+            channel_id: None
+        }).await
+    }
+
+    pub async fn send_channel_message(&self, channel_id: u32, message: &str) -> MumbleResult<()> {
+        self.send_action(MumbleAction::SendMessage {
+            message: message.to_owned(),
+            channel_id: Some(channel_id)
+        }).await
+    }
+
+    // the server has the final say, this only catches what we already know
+    // it would refuse
+    async fn check_enter(&self, channel_id: u32) -> MumbleResult<Channel> {
+        let session_id = self.session_id().await;
+        let channels = self.shared.channels.lock().await;
+
+        let channel = match channels.get(channel_id) {
+            Some(channel) => channel.clone(),
+            None => return Err(MumbleError::ChannelNotFound(channel_id.to_string()))
+        };
+        let path = channels.path(channel_id).unwrap_or_default();
+
+        if channel.is_enter_restricted && !channel.can_enter {
+            return Err(MumbleError::ChannelEnterDenied(path));
+        }
+
+        // a limit of 0 leaves it to the server wide default, which we do not know
+        if channel.max_users > 0 {
+            let users = self.shared.users.lock().await;
+            let occupants = users.in_channel(channel_id).iter()
+                .filter(|user| user.session != session_id)
+                .count();
+
+            if occupants >= channel.max_users as usize {
+                return Err(MumbleError::ChannelFull(path));
+            }
+        }
+
+        Ok(channel)
+    }
+
+    /// A handle whose actions return as soon as they are queued, without
+    /// waiting for the server. Channel administration still waits, as it
+    /// returns the channel the server ended up with.
+    pub fn without_confirmation(&self) -> Self {
+        Self {
+            confirm: false,
+            ..self.clone()
+        }
+    }
+
+    /// A handle whose actions give up waiting for the server after
+    /// `timeout`. The action may still take effect later.
+    pub fn with_action_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            action_timeout: timeout,
+            ..self.clone()
+        }
+    }
+
+    async fn send_action(&self, action: MumbleAction) -> MumbleResult<()> {
+        if !self.confirm {
+            let command = Command { action, reply: None };
+            return self.commands.send(command).await.map_err(|_| MumbleError::Disconnected);
+        }
+
+        self.request(action).await.map(drop)
+    }
+
+    // Resolves once the server confirmed the action, fails with the server's
+    // PermissionDenied, or with Disconnected when the connection dropped
+    // before an answer. Queueing waits for room when the connection falls
+    // behind, which counts towards the timeout.
+    async fn request(&self, action: MumbleAction) -> MumbleResult<Option<Channel>> {
+        tokio::time::timeout(self.action_timeout, async {
+            let (reply, confirmation) = oneshot::channel();
+            let command = Command { action, reply: Some(reply) };
+
+            self.commands.send(command).await.map_err(|_| MumbleError::Disconnected)?;
+            confirmation.await.map_err(|_| MumbleError::Disconnected)?
+        }).await?
+    }
+}
+
+#[cfg(test)]
+impl ClientHandle {
+    // a handle whose actions go nowhere
+    pub(crate) fn detached() -> Self {
+        let (tx, _) = mpsc::channel(1);
+
+        Self {
+            commands: tx,
+            shared: Shared::new(UserInfo::default()),
+            confirm: false,
+            action_timeout: DEFAULT_ACTION_TIMEOUT
+        }
+    }
+}
+
+
+pub struct MumbleClient {
+    config: Arc<ConnectionConfig>,
+    commands: Sender<Command>,
+    shared: Shared,
+    task: JoinHandle<()>,
+    events: broadcast::Sender<Event>
+}
+
+impl MumbleClient {
+
+    pub fn builder(address: &str) -> MumbleClientBuilder {
+        MumbleClientBuilder::new(address)
+    }
+
+    /// Connects to the server named by a `mumble://` URL with its credentials
+    /// and joins the channel path it names.
+    pub async fn connect_url(url: &str) -> MumbleResult<Self> {
+        let url = MumbleUrl::parse(url)?;
+        MumbleClientBuilder::from_url(&url).connect().await
+    }
+
+    pub(crate) async fn connect(config: ConnectionConfig) -> MumbleResult<Self> {
+        let stream = connection::open_stream(&config).await?;
+        Self::connect_with_stream(config, stream).await
+    }
+
+    /// Logs in over `stream`, which must already be connected (and encrypted,
+    /// if the server expects TLS). Reconnecting would bypass the stream, so
+    /// `config.reconnect` should be cleared by the caller.
+    pub(crate) async fn connect_with_stream<S: Transport>(config: ConnectionConfig, stream: S) -> MumbleResult<Self> {
+
+        let (reader, mut writer) = connection::split_stream(Box::new(stream));
+        connection::authenticate(&mut writer, &config).await?;
+
+        let (commands, commands_rx) = mpsc::channel(COMMAND_CAPACITY);
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let (synchronized, synchronized_rx) = oneshot::channel();
+
+        let shared = Shared::new(UserInfo {
+            name: config.username.clone(),
+            ..UserInfo::default()
+        });
+
+        let config = Arc::new(config);
+        let connection = Connection::new(
+            Arc::clone(&config), reader, writer, commands_rx, shared.clone(), events.clone(), synchronized
+        );
+
+        // dropping the client on error aborts the connection task
+        let client = Self {
+            config,
+            commands,
+            shared,
+            task: tokio::spawn(connection.run()),
+            events
+        };
+
+        match tokio::time::timeout(client.config.sync_timeout, synchronized_rx).await? {
+            Ok(result) => result?,
+            Err(_) => return Err(MumbleError::Disconnected)
+        }
+
+        Ok(client)
+    }
+
+
+    pub fn identity(&self) -> Option<&Identity> {
+        self.config.tls_config.identity.as_ref()
+    }
+
+    /// Whether voice is sent through the control connection (`UDPTunnel`)
+    /// instead of UDP. Always the case behind a proxy, since only the TCP
+    /// connection is tunnelled.
+    pub fn voice_over_tcp(&self) -> bool {
+        self.config.force_tcp_voice || self.config.proxy.is_some()
+    }
+
+    /// Returns a new, independent receiver of server events.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Returns a handle that can issue actions independently of `self`.
+    pub fn handle(&self) -> ClientHandle {
+        ClientHandle {
+            commands: self.commands.clone(),
+            shared: self.shared.clone(),
+            confirm: true,
+            action_timeout: self.config.action_timeout
+        }
+    }
+
+    /// Feeds every event to `handler` until the connection is gone for good.
+    /// Callbacks run one at a time, in the order the server sent them.
+    pub async fn run_with_handler<H: EventHandler>(&self, handler: H) -> MumbleResult<()> {
+        let mut events = self.subscribe();
+        let handle = self.handle();
+
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break
+            };
+
+            let disconnected = matches!(event, Event::Disconnected);
+            handler::dispatch(&handler, &handle, event).await;
+
+            if disconnected {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn set_comment(&mut self, comment: &str) -> MumbleResult<()> {
+        self.handle().set_comment(comment).await
+    }
+
+    pub async fn set_self_mute(&mut self, self_mute: bool) -> MumbleResult<()> {
+        self.handle().set_self_mute(self_mute).await
+    }
+
+    pub async fn set_self_deaf(&mut self, self_deaf: bool) -> MumbleResult<()> {
+        self.handle().set_self_deaf(self_deaf).await
+    }
+
+    /// Registers voice target `id` (1-30) for whispering and shouting. An
+    /// empty target list unregisters it.
+    pub async fn register_voice_target(&mut self, id: u32, targets: Vec<voice_target::Target>) -> MumbleResult<()> {
+        self.handle().register_voice_target(id, targets).await
+    }
+
+    pub async fn listen_to_channel(&mut self, channel: &Channel) -> MumbleResult<()> {
+        self.handle().listen_to_channel(channel).await
+    }
+
+    pub async fn stop_listening_to_channel(&mut self, channel: &Channel) -> MumbleResult<()> {
+        self.handle().stop_listening_to_channel(channel).await
+    }
+
+    pub async fn get_channels(&self) -> ChannelList {
+        self.handle().get_channels().await
+    }
+
+    pub async fn get_users(&self) -> UserList {
+        self.handle().get_users().await
+    }
+
+    pub async fn get_user(&self, session: u32) -> Option<User> {
+        self.handle().get_user(session).await
+    }
+
+    pub async fn stats(&self) -> ConnectionStats {
+        self.handle().stats().await
+    }
+
+    pub async fn join_channel(&mut self, channel: Channel) -> MumbleResult<()> {
+        self.handle().join_channel(channel).await
+    }
+
+    pub async fn join_channel_path(&mut self, path: &str, case_sensitive: bool) -> MumbleResult<()> {
+        self.handle().join_channel_path(path, case_sensitive).await
+    }
+
+    pub async fn create_channel(&mut self, parent: &Channel, name: &str, temporary: bool) -> MumbleResult<Channel> {
+        self.handle().create_channel(parent, name, temporary).await
+    }
+
+    pub async fn rename_channel(&mut self, channel: &Channel, name: &str) -> MumbleResult<Channel> {
+        self.handle().rename_channel(channel, name).await
+    }
+
+    pub async fn set_channel_description(&mut self, channel: &Channel, description: &str) -> MumbleResult<Channel> {
+        self.handle().set_channel_description(channel, description).await
+    }
+
+    pub async fn set_channel_position(&mut self, channel: &Channel, position: i32) -> MumbleResult<Channel> {
+        self.handle().set_channel_position(channel, position).await
+    }
+
+    pub async fn set_channel_max_users(&mut self, channel: &Channel, max_users: u32) -> MumbleResult<Channel> {
+        self.handle().set_channel_max_users(channel, max_users).await
+    }
+
+    pub async fn move_channel(&mut self, channel: &Channel, parent: &Channel) -> MumbleResult<Channel> {
+        self.handle().move_channel(channel, parent).await
+    }
+
+    pub async fn link_channels(&mut self, channel: &Channel, other: &Channel) -> MumbleResult<Channel> {
+        self.handle().link_channels(channel, other).await
+    }
+
+    pub async fn unlink_channels(&mut self, channel: &Channel, other: &Channel) -> MumbleResult<Channel> {
+        self.handle().unlink_channels(channel, other).await
+    }
+
+    pub async fn remove_channel(&mut self, channel: &Channel) -> MumbleResult<()> {
+        self.handle().remove_channel(channel).await
+    }
+
+    pub async fn send_message(&mut self, message: &str) -> MumbleResult<()> {
+        self.handle().send_message(message).await
+    }
+
+    pub async fn send_image(&mut self, file_path: &str) -> MumbleResult<()> {
+
+        let mut contents = File::open(file_path)?;
+        let mut buffer: Vec<u8> = Vec::new();
+        contents.read_to_end(&mut buffer)?;
+        let contents = base64::encode(&buffer);
+
+        // TODO:  send other image types
+
+        let mut data = String::new();
+        data.push_str("<img src=\"data:image/JPEG;base64,");
+        data.push_str(&contents);
+        data.push_str("\"/>");
+
+        self.send_message(&data).await?;
+
+        Ok(())
+    }
+
+    pub async fn shutdown(&mut self) -> MumbleResult<()> {
+        self.task.abort();
+
+        Ok(())
+    }
+}
+
+
+impl Drop for MumbleClient {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::MessageType;
+    use crate::socket::{SocketReader, SocketWriter};
+    use crate::deny::DenyType;
+    use crate::reject::RejectType;
+    use crate::message::ControlMessage;
+
+    use std::convert::TryFrom;
+
+    type ServerReader = SocketReader<tokio::io::ReadHalf<tokio::io::DuplexStream>>;
+    type ServerWriter = SocketWriter<tokio::io::WriteHalf<tokio::io::DuplexStream>>;
+
+    // reads the client's Version and Authenticate, returning the latter
+    async fn login(server_stream: tokio::io::DuplexStream) -> (ServerReader, ServerWriter, Authenticate) {
+        let (reader, writer) = tokio::io::split(server_stream);
+        let mut reader = SocketReader::new(reader);
+        let writer = SocketWriter::new(writer);
+
+        let version: Version = reader.read_packet().await.unwrap().to_message().unwrap();
+        assert_eq!(version.version, Some(connection::MUMBLE_VERSION));
+
+        let authenticate = reader.read_packet().await.unwrap().to_message().unwrap();
+
+        (reader, writer, authenticate)
+    }
+
+    // reads the login, then announces the channels Root and Stage (3) with
+    // carol (9) in it and synchronizes `session`
+    async fn accept(server_stream: tokio::io::DuplexStream, session: u32) -> (ServerReader, ServerWriter) {
+        let (reader, mut writer, _) = login(server_stream).await;
+
+        for (channel_id, name) in &[(0, "Root"), (3, "Stage")] {
+            let channel_state = ChannelState {
+                channel_id: Some(*channel_id),
+                parent: Some(0),
+                name: Some((*name).to_owned()),
+                ..ChannelState::default()
+            };
+            writer.write_message(MessageType::ChannelState, &channel_state).await.unwrap();
+        }
+
+        let carol = UserState {
+            session: Some(9),
+            name: Some("carol".to_owned()),
+            channel_id: Some(3),
+            ..UserState::default()
+        };
+        writer.write_message(MessageType::UserState, &carol).await.unwrap();
+
+        let server_sync = ServerSync {
+            session: Some(session),
+            ..ServerSync::default()
+        };
+        writer.write_message(MessageType::ServerSync, &server_sync).await.unwrap();
+
+        (reader, writer)
+    }
+
+    // reads the login and synchronizes session 1 if `sync` is set, then
+    // swallows everything the client sends without ever replying
+    fn silent_server(server_stream: tokio::io::DuplexStream, sync: bool) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut reader = match sync {
+                true => accept(server_stream, 1).await.0,
+                false => login(server_stream).await.0
+            };
+
+            while reader.read_packet().await.is_ok() {}
+        })
+    }
+
+    // synchronizes session 1 and answers every message with whatever
+    // `respond` returns
+    fn scripted_server<F>(server_stream: tokio::io::DuplexStream, mut respond: F) -> JoinHandle<()>
+        where F: FnMut(ControlMessage) -> Option<ControlMessage> + Send + 'static {
+        tokio::spawn(async move {
+            let (mut reader, mut writer) = accept(server_stream, 1).await;
+
+            while let Ok(packet) = reader.read_packet().await {
+                let message = ControlMessage::try_from(&packet).unwrap();
+                if let Some(reply) = respond(message) {
+                    writer.write_packet(&reply.to_packet().unwrap()).await.unwrap();
+                }
+            }
+        })
+    }
+
+    // the next message that is not a ping
+    async fn next_message(reader: &mut ServerReader) -> ControlMessage {
+        loop {
+            match ControlMessage::try_from(&reader.read_packet().await.unwrap()).unwrap() {
+                ControlMessage::Ping(_) => continue,
+                message => return message
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_over_duplex() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        let server = tokio::spawn(async move {
+            let (mut reader, mut writer, authenticate) = login(server_stream).await;
+            assert_eq!(authenticate.username.as_deref(), Some("bot"));
+
+            let root = ChannelState {
+                channel_id: Some(0),
+                name: Some("Root".to_owned()),
+                ..ChannelState::default()
+            };
+            writer.write_message(MessageType::ChannelState, &root).await.unwrap();
+
+            let user_state = UserState {
+                session: Some(7),
+                name: Some("bot".to_owned()),
+                channel_id: Some(0),
+                ..UserState::default()
+            };
+            writer.write_message(MessageType::UserState, &user_state).await.unwrap();
+
+            let server_sync = ServerSync {
+                session: Some(7),
+                ..ServerSync::default()
+            };
+            writer.write_message(MessageType::ServerSync, &server_sync).await.unwrap();
+
+            let text_message: TextMessage = reader.read_packet().await.unwrap().to_message().unwrap();
+
+            // the ping sent right behind the message confirms it
+            let ping = reader.read_packet().await.unwrap();
+            assert!(matches!(ping.message_type(), MessageType::Ping));
+            writer.write_packet(&ping).await.unwrap();
+
+            text_message.message
+        });
+
+        let mut client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        assert_eq!(client.handle().session_id().await, 7);
+        assert!(client.get_channels().await.find("Root").is_some());
+        assert_eq!(client.get_users().await.find("bot").map(|user| user.session), Some(7));
+
+        client.send_message("hello").await.unwrap();
+        assert_eq!(server.await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_sync_timeout() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let _server = silent_server(server_stream, false);
+
+        let result = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .sync_timeout(Duration::from_millis(200))
+            .connect_with_stream(client_stream)
+            .await;
+
+        assert!(matches!(result, Err(MumbleError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn test_rejected() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        let _server = tokio::spawn(async move {
+            let (mut reader, mut writer, _) = login(server_stream).await;
+
+            let reject = Reject {
+                r#type: Some(RejectType::WrongUserPW as i32),
+                reason: Some("Wrong password".to_owned())
+            };
+            writer.write_message(MessageType::Reject, &reject).await.unwrap();
+
+            while reader.read_packet().await.is_ok() {}
+        });
+
+        let result = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .password("hunter2")
+            .connect_with_stream(client_stream)
+            .await;
+
+        match result {
+            Err(MumbleError::Rejected(reject)) => {
+                assert_eq!(reject.reject_type, Some(RejectType::WrongUserPW));
+                assert_eq!(reject.reason.as_deref(), Some("Wrong password"));
+            },
+            result => panic!("expected a rejection, got {:?}", result.err())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_liveness_timeout() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let _server = silent_server(server_stream, true);
+
+        let client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .liveness_timeout(Duration::from_millis(500))
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        let mut events = client.subscribe();
+        let disconnected = async {
+            while !matches!(events.recv().await, Ok(Event::Disconnected)) {}
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), disconnected).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_liveness_with_echoed_pings() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let _server = scripted_server(server_stream, |message| match message {
+            ControlMessage::Ping(ping) => Some(ControlMessage::Ping(ping)),
+            _ => None
+        });
+
+        let client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .liveness_timeout(Duration::from_millis(300))
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        // pings go out often enough that the session outlives the timeout
+        let mut events = client.subscribe();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event, Event::Disconnected | Event::Reconnecting { .. }));
+        }
+        assert!(client.stats().await.tcp_packets >= 3);
+    }
+
+    #[tokio::test]
+    async fn test_actions_are_sent_in_order() {
+        let (client_stream, server_stream) = tokio::io::duplex(1024);
+
+        let server = tokio::spawn(async move {
+            let (mut reader, _writer) = accept(server_stream, 1).await;
+
+            let mut messages = Vec::new();
+            while messages.len() < 1000 {
+                let packet = reader.read_packet().await.unwrap();
+                if let MessageType::TextMessage = packet.message_type() {
+                    messages.push(packet.to_message::<TextMessage>().unwrap().message);
+                }
+            }
+            messages
+        });
+
+        let client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        // far more than the queue holds, so senders have to wait their turn
+        let handle = client.handle().without_confirmation();
+        for i in 0..1000 {
+            handle.send_message(&i.to_string()).await.unwrap();
+        }
+
+        let expected: Vec<String> = (0..1000).map(|i: u32| i.to_string()).collect();
+        assert_eq!(server.await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_action_confirmation() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        // mutes are echoed, deafening and listening are denied and pings are
+        // only answered for text messages, so each action can only be
+        // settled one way
+        let mut echo_ping = false;
+        let _server = scripted_server(server_stream, move |message| match message {
+            ControlMessage::UserState(user_state) if user_state.self_deaf.is_some() => {
+                Some(ControlMessage::PermissionDenied(PermissionDenied {
+                    r#type: Some(DenyType::Permission as i32),
+                    session: Some(1),
+                    ..PermissionDenied::default()
+                }))
+            },
+            ControlMessage::UserState(user_state) if !user_state.listening_channel_add.is_empty() => {
+                Some(ControlMessage::PermissionDenied(PermissionDenied {
+                    r#type: Some(DenyType::ChannelListenerLimit as i32),
+                    ..PermissionDenied::default()
+                }))
+            },
+            ControlMessage::UserState(user_state) => Some(ControlMessage::UserState(user_state)),
+            ControlMessage::TextMessage(_) => {
+                echo_ping = true;
+                None
+            },
+            ControlMessage::Ping(ping) if echo_ping => {
+                echo_ping = false;
+                Some(ControlMessage::Ping(ping))
+            },
+            _ => None
+        });
+
+        let client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .action_timeout(Duration::from_secs(5))
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        let handle = client.handle();
+        handle.set_self_mute(true).await.unwrap();
+        handle.send_message("hello").await.unwrap();
+
+        match handle.set_self_deaf(true).await {
+            Err(MumbleError::PermissionDenied(deny)) => assert_eq!(deny.deny_type, Some(DenyType::Permission)),
+            result => panic!("expected a denial, got {:?}", result.err())
+        }
+
+        match handle.listen_to_channel(&Channel::new(3)).await {
+            Err(MumbleError::PermissionDenied(deny)) => assert_eq!(deny.deny_type, Some(DenyType::ChannelListenerLimit)),
+            result => panic!("expected a denial, got {:?}", result.err())
+        }
+
+        // a denied change is not kept, and would not be restored after a
+        // reconnect
+        let user_info = handle.shared.user_info.lock().await;
+        assert!(user_info.self_mute);
+        assert!(!user_info.self_deaf);
+        assert!(user_info.listening_channels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_action_timeout() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let _server = scripted_server(server_stream, |_| None);
+
+        let client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        let handle = client.handle().with_action_timeout(Duration::from_millis(200));
+        assert!(matches!(handle.set_comment("hello").await, Err(MumbleError::Timeout)));
+
+        // nobody waits for an answer that never comes
+        handle.without_confirmation().set_comment("hello").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_join_channel_checks() {
+        let handle = ClientHandle::detached();
+        handle.shared.user_info.lock().await.session_id = 1;
+
+        {
+            let mut channels = handle.shared.channels.lock().await;
+            for (id, name, max_users, can_enter) in &[(0, "Root", 0, true), (1, "Lobby", 1, true), (2, "Staff", 0, false)] {
+                channels.merge(&ChannelState {
+                    channel_id: Some(*id),
+                    parent: Some(0),
+                    name: Some((*name).to_owned()),
+                    max_users: Some(*max_users),
+                    is_enter_restricted: Some(!*can_enter),
+                    can_enter: Some(*can_enter),
+                    ..ChannelState::default()
+                });
+            }
+
+            let mut users = handle.shared.users.lock().await;
+            users.merge(&UserState { session: Some(1), channel_id: Some(1), ..UserState::default() });
+        }
+
+        // the detached handle has nobody listening, so a join that passes
+        // the checks fails with Disconnected instead
+        assert!(matches!(handle.join_channel_path("lobby", false).await, Err(MumbleError::Disconnected)));
+        assert!(matches!(handle.join_channel_path("lobby", true).await, Err(MumbleError::ChannelNotFound(_))));
+        assert!(matches!(handle.join_channel_path("Staff", true).await, Err(MumbleError::ChannelEnterDenied(path)) if path == "/Staff"));
+
+        handle.shared.users.lock().await.merge(&UserState { session: Some(2), channel_id: Some(1), ..UserState::default() });
+        assert!(matches!(handle.join_channel_path("Lobby", true).await, Err(MumbleError::ChannelFull(_))));
+    }
+
+    #[tokio::test]
+    async fn test_channel_administration() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        // a server that allows everything except names starting with "Admin"
+        // and ignores channels below the missing channel 99
+        let mut next_id = 5;
+        let _server = scripted_server(server_stream, move |message| match message {
+            ControlMessage::ChannelState(channel_state) if channel_state.parent == Some(99) => None,
+            ControlMessage::ChannelState(channel_state) if channel_state.name.as_deref().is_some_and(|name| name.starts_with("Admin")) => {
+                Some(ControlMessage::PermissionDenied(PermissionDenied {
+                    r#type: Some(DenyType::Permission as i32),
+                    ..PermissionDenied::default()
+                }))
+            },
+            ControlMessage::ChannelState(mut channel_state) => {
+                if channel_state.channel_id.is_none() {
+                    channel_state.channel_id = Some(next_id);
+                    next_id += 1;
+                }
+                Some(ControlMessage::ChannelState(channel_state))
+            },
+            ControlMessage::ChannelRemove(channel_remove) => Some(ControlMessage::ChannelRemove(channel_remove)),
+            ControlMessage::Ping(ping) => Some(ControlMessage::Ping(ping)),
+            _ => None
+        });
+
+        let client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .action_timeout(Duration::from_secs(5))
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        let handle = client.handle();
+        let root = Channel::new(0);
+
+        let channel = handle.create_channel(&root, "Ops", true).await.unwrap();
+        assert_eq!((channel.id, channel.parent, channel.temporary), (5, 0, true));
+
+        // the reply is the whole channel, not just the changed field
+        let channel = handle.set_channel_max_users(&channel, 4).await.unwrap();
+        assert_eq!((channel.name.as_str(), channel.max_users), ("Ops", 4));
+
+        assert!(matches!(handle.rename_channel(&channel, "Admins").await, Err(MumbleError::PermissionDenied(_))));
+        assert!(matches!(handle.create_channel(&Channel::new(99), "Orphan", false).await, Err(MumbleError::ChannelNotFound(id)) if id == "99"));
+
+        // the server only echoes one side of the link
+        let stage = handle.create_channel(&root, "Stage", false).await.unwrap();
+        let channel = handle.link_channels(&channel, &stage).await.unwrap();
+        assert_eq!(channel.links, vec![stage.id]);
+        assert_eq!(handle.get_channels().await.get(stage.id).unwrap().links, vec![channel.id]);
+
+        let channel = handle.unlink_channels(&channel, &stage).await.unwrap();
+        assert!(channel.links.is_empty());
+
+        handle.remove_channel(&channel).await.unwrap();
+        assert!(handle.get_channels().await.get(5).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_restores_session() {
+        let (streams_tx, mut streams) = mpsc::unbounded_channel();
+        let open_stream: connection::OpenStream = Arc::new(move || {
+            let (client_stream, server_stream) = tokio::io::duplex(4096);
+            streams_tx.send(server_stream).unwrap_or_default();
+            Box::new(client_stream)
+        });
+
+        let mut config = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .reconnect(ReconnectPolicy {
+                initial_delay: Duration::from_millis(10),
+                jitter: 0.0,
+                ..ReconnectPolicy::default()
+            })
+            .resolve_config()
+            .unwrap();
+        config.open_stream = Some(open_stream);
+
+        // the first server confirms everything until it goes away
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let first = tokio::spawn(async move {
+            let (mut reader, mut writer) = accept(server_stream, 1).await;
+            while let Ok(packet) = reader.read_packet().await {
+                if let MessageType::UserState | MessageType::Ping = packet.message_type() {
+                    writer.write_packet(&packet).await.unwrap();
+                }
+            }
+        });
+
+        let client = MumbleClient::connect_with_stream(config, client_stream).await.unwrap();
+        let mut events = client.subscribe();
+        let handle = client.handle();
+        let stage = client.get_channels().await.get(3).cloned().unwrap();
+
+        let targets = vec![voice_target::Target {
+            channel_id: Some(3),
+            ..voice_target::Target::default()
+        }];
+
+        handle.join_channel(stage.clone()).await.unwrap();
+        handle.set_comment("hi").await.unwrap();
+        handle.set_self_mute(true).await.unwrap();
+        handle.set_self_deaf(true).await.unwrap();
+        handle.register_voice_target(1, targets.clone()).await.unwrap();
+        handle.listen_to_channel(&stage).await.unwrap();
+
+        first.abort();
+
+        // murmur still holds the old session, so the name is taken at first
+        let (reader, mut writer, _) = login(streams.recv().await.unwrap()).await;
+
+        let reject = Reject {
+            r#type: Some(RejectType::UsernameInUse as i32),
+            reason: None
+        };
+        writer.write_message(MessageType::Reject, &reject).await.unwrap();
+        drop((reader, writer));
+
+        let (mut reader, mut writer) = accept(streams.recv().await.unwrap(), 2).await;
+
+        match next_message(&mut reader).await {
+            ControlMessage::UserState(user_state) => {
+                assert_eq!(user_state.session, Some(2));
+                assert_eq!(user_state.channel_id, Some(3));
+                assert_eq!(user_state.comment.as_deref(), Some("hi"));
+                assert_eq!((user_state.self_mute, user_state.self_deaf), (Some(true), Some(true)));
+                assert_eq!(user_state.listening_channel_add, vec![3]);
+            },
+            message => panic!("expected the restored user state, got {:?}", message)
+        }
+
+        match next_message(&mut reader).await {
+            ControlMessage::VoiceTarget(voice_target) => assert_eq!((voice_target.id, voice_target.targets), (Some(1), targets)),
+            message => panic!("expected the restored voice target, got {:?}", message)
+        }
+
+        let reconnected = async {
+            while !matches!(events.recv().await, Ok(Event::Reconnecting { .. })) {}
+
+            let mut seen = Vec::new();
+            loop {
+                match events.recv().await {
+                    Ok(Event::Reconnected) => return seen,
+                    Ok(event) => seen.push(event),
+                    Err(_) => {}
+                }
+            }
+        };
+        let seen = tokio::time::timeout(Duration::from_secs(5), reconnected).await.unwrap();
+        assert_eq!(handle.session_id().await, 2);
+
+        // the resent channels and users were there all along
+        for event in seen {
+            assert!(!matches!(event, Event::ChannelCreated { .. } | Event::UserConnected { .. }), "unexpected {:?}", event);
+        }
+        assert_eq!(handle.get_user(9).await.map(|user| user.channel_id), Some(3));
+
+        // the channel is full by now, and the denial of the restored state
+        // only arrives once the next action was sent
+        let comment = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.set_comment("bye").await }
+        });
+
+        let user_state = match next_message(&mut reader).await {
+            ControlMessage::UserState(user_state) => user_state,
+            message => panic!("expected the new comment, got {:?}", message)
+        };
+
+        let denied = PermissionDenied {
+            r#type: Some(DenyType::ChannelFull as i32),
+            ..PermissionDenied::default()
+        };
+        writer.write_message(MessageType::PermissionDenied, &denied).await.unwrap();
+        writer.write_message(MessageType::UserState, &user_state).await.unwrap();
+
+        comment.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_by_url() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        let server = tokio::spawn(async move {
+            let (mut reader, mut writer) = accept(server_stream, 1).await;
+
+            let user_state = match next_message(&mut reader).await {
+                ControlMessage::UserState(user_state) => user_state,
+                message => panic!("expected a channel move, got {:?}", message)
+            };
+            writer.write_message(MessageType::UserState, &user_state).await.unwrap();
+
+            while reader.read_packet().await.is_ok() {}
+            user_state
+        });
+
+        let url: MumbleUrl = "mumble://bot@localhost/Stage".parse().unwrap();
+        let client = MumbleClientBuilder::from_url(&url)
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+        drop(client);
+
+        let user_state = server.await.unwrap();
+        assert_eq!((user_state.session, user_state.channel_id), (Some(1), Some(3)));
+
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+        let _server = silent_server(server_stream, true);
+
+        let url: MumbleUrl = "mumble://bot@localhost/Stage/Nope".parse().unwrap();
+        let result = MumbleClientBuilder::from_url(&url)
+            .connect_with_stream(client_stream)
+            .await;
+
+        assert!(matches!(result, Err(MumbleError::ChannelNotFound(path)) if path == "Stage/Nope"));
+    }
+
+    #[tokio::test]
+    async fn test_event_stream() {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        // waits for the client to be listening, then plays out a user
+        // joining, moving, muting and leaving
+        let _server = tokio::spawn(async move {
+            let (mut reader, mut writer) = accept(server_stream, 1).await;
+            next_message(&mut reader).await;
+
+            let messages: Vec<ControlMessage> = vec![
+                ChannelState { channel_id: Some(4), parent: Some(0), name: Some("Lounge".to_owned()), ..ChannelState::default() }.into(),
+                ChannelState { channel_id: Some(4), name: Some("Bar".to_owned()), ..ChannelState::default() }.into(),
+                UserState { session: Some(5), name: Some("alice".to_owned()), channel_id: Some(0), ..UserState::default() }.into(),
+                UserState { session: Some(5), actor: Some(5), channel_id: Some(4), ..UserState::default() }.into(),
+                UserState { session: Some(5), self_mute: Some(true), ..UserState::default() }.into(),
+                UserRemove { session: 5, reason: Some("bye".to_owned()), ..UserRemove::default() }.into()
+            ];
+
+            for message in messages {
+                writer.write_packet(&message.to_packet().unwrap()).await.unwrap();
+            }
+
+            while reader.read_packet().await.is_ok() {}
+        });
+
+        let client = MumbleClient::builder("localhost:64738")
+            .username("bot")
+            .connect_with_stream(client_stream)
+            .await
+            .unwrap();
+
+        let subscribers = vec![client.subscribe(), client.subscribe()];
+        client.handle().without_confirmation().send_message("go").await.unwrap();
+
+        let expected = vec![
+            "created 4 Lounge",
+            "updated 4 Bar",
+            "connected 5 alice",
+            "moved 5 0 -> 4",
+            "changed 5 mute",
+            "disconnected 5 bye"
+        ];
+
+        for mut events in subscribers {
+            let mut seen = Vec::new();
+
+            while seen.len() < expected.len() {
+                let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+                seen.push(match event {
+                    Event::ChannelCreated { channel } => format!("created {} {}", channel.id, channel.name),
+                    Event::ChannelUpdated { channel } => format!("updated {} {}", channel.id, channel.name),
+                    Event::UserConnected { session, state } => format!("connected {} {}", session, state.name.unwrap_or_default()),
+                    Event::UserMoved { session, from, to, .. } => format!("moved {} {} -> {}", session, from, to),
+                    Event::UserStateChanged { session, state } if state.self_mute == Some(true) => format!("changed {} mute", session),
+                    Event::UserDisconnected { session, reason, .. } => format!("disconnected {} {}", session, reason.unwrap_or_default()),
+                    event => panic!("unexpected event {:?}", event)
+                });
+            }
+
+            assert_eq!(seen, expected);
+        }
+    }
+}
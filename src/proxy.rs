@@ -0,0 +1,317 @@
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::tls::split_address;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use std::net::IpAddr;
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTHENTICATION: u8 = 0x00;
+const SOCKS_USERNAME_PASSWORD: u8 = 0x02;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+const SOCKS_DOMAIN_NAME: u8 = 3;
+const SOCKS_IPV6: u8 = 4;
+
+const MAX_HTTP_RESPONSE_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String
+}
+
+/// A proxy the control connection is tunnelled through before the TLS
+/// handshake. `address` is the proxy's own `host:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Proxy {
+    Socks5 {
+        address: String,
+        credentials: Option<ProxyCredentials>
+    },
+    HttpConnect {
+        address: String,
+        credentials: Option<ProxyCredentials>
+    }
+}
+
+impl Proxy {
+
+    pub fn socks5(address: &str) -> Self {
+        Proxy::Socks5 {
+            address: address.to_owned(),
+            credentials: None
+        }
+    }
+
+    pub fn http_connect(address: &str) -> Self {
+        Proxy::HttpConnect {
+            address: address.to_owned(),
+            credentials: None
+        }
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        let new_credentials = Some(ProxyCredentials {
+            username: username.to_owned(),
+            password: password.to_owned()
+        });
+
+        match &mut self {
+            Proxy::Socks5 { credentials, .. } | Proxy::HttpConnect { credentials, .. } => *credentials = new_credentials
+        }
+
+        self
+    }
+
+    pub fn address(&self) -> &str {
+        match self {
+            Proxy::Socks5 { address, .. } | Proxy::HttpConnect { address, .. } => address
+        }
+    }
+
+    /// Opens a connection to the proxy and asks it for a tunnel to `target`.
+    pub(crate) async fn connect(&self, target: &str) -> MumbleResult<TcpStream> {
+        let mut stream = TcpStream::connect(self.address()).await?;
+
+        match self {
+            Proxy::Socks5 { credentials, .. } => socks5_handshake(&mut stream, target, credentials.as_ref()).await?,
+            Proxy::HttpConnect { credentials, .. } => http_connect_handshake(&mut stream, target, credentials.as_ref()).await?
+        }
+
+        Ok(stream)
+    }
+}
+
+fn proxy_error(message: &str) -> MumbleError {
+    MumbleError::Proxy(message.to_owned())
+}
+
+// `target` with an explicit port, as HTTP proxies expect it
+fn target_authority(target: &str) -> MumbleResult<String> {
+    let (host, port) = split_address(target)?;
+
+    match host.contains(':') {
+        true => Ok(format!("[{}]:{}", host, port)),
+        false => Ok(format!("{}:{}", host, port))
+    }
+}
+
+async fn socks5_handshake(stream: &mut TcpStream, target: &str, credentials: Option<&ProxyCredentials>) -> MumbleResult<()> {
+
+    let method = match credentials {
+        Some(_) => SOCKS_USERNAME_PASSWORD,
+        None => SOCKS_NO_AUTHENTICATION
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("Proxy does not speak SOCKS5"));
+    }
+    if reply[1] != method {
+        return Err(proxy_error("Proxy refused the authentication method"));
+    }
+
+    // RFC 1929 username/password sub-negotiation
+    if let Some(credentials) = credentials {
+        if credentials.username.len() > 255 || credentials.password.len() > 255 {
+            return Err(proxy_error("Proxy username or password is too long"));
+        }
+
+        let mut request = vec![1, credentials.username.len() as u8];
+        request.extend_from_slice(credentials.username.as_bytes());
+        request.push(credentials.password.len() as u8);
+        request.extend_from_slice(credentials.password.as_bytes());
+        stream.write_all(&request).await?;
+
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(proxy_error("Proxy rejected the credentials"));
+        }
+    }
+
+    let (host, port) = split_address(target)?;
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(SOCKS_IPV4);
+            request.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            request.push(SOCKS_IPV6);
+            request.extend_from_slice(&ip.octets());
+        },
+        Err(_) if host.len() <= 255 => {
+            // let the proxy resolve the name
+            request.push(SOCKS_DOMAIN_NAME);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        },
+        Err(_) => return Err(proxy_error("Server host name is too long"))
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        let reason = match reply[1] {
+            1 => "general failure",
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "unknown error"
+        };
+        return Err(MumbleError::Proxy(format!("Proxy could not connect to the server: {}", reason)));
+    }
+
+    // skip the bound address, which is of no use to us
+    let address_length = match reply[3] {
+        SOCKS_IPV4 => 4,
+        SOCKS_IPV6 => 16,
+        SOCKS_DOMAIN_NAME => stream.read_u8().await? as usize,
+        _ => return Err(proxy_error("Proxy sent a malformed reply"))
+    };
+    let mut bound_address = vec![0u8; address_length + 2];
+    stream.read_exact(&mut bound_address).await?;
+
+    Ok(())
+}
+
+async fn http_connect_handshake(stream: &mut TcpStream, target: &str, credentials: Option<&ProxyCredentials>) -> MumbleResult<()> {
+
+    let authority = target_authority(target)?;
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", authority, authority);
+    if let Some(credentials) = credentials {
+        let token = base64::encode(format!("{}:{}", credentials.username, credentials.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // read byte by byte so nothing after the headers is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE_SIZE {
+            return Err(proxy_error("Proxy response is too large"));
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some(_) => Err(MumbleError::Proxy(format!("Proxy refused the tunnel: {}", status_line))),
+        None => Err(proxy_error("Proxy sent a malformed reply"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    // accepts one client, checks the handshake and then echoes everything back
+    async fn socks5_proxy(expected_target: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [SOCKS_VERSION, 1, SOCKS_USERNAME_PASSWORD]);
+            stream.write_all(&[SOCKS_VERSION, SOCKS_USERNAME_PASSWORD]).await.unwrap();
+
+            let mut authentication = [0u8; 11];
+            stream.read_exact(&mut authentication).await.unwrap();
+            let accepted = &authentication == b"\x01\x03bot\x05hello";
+            stream.write_all(&[1, if accepted { 0 } else { 1 }]).await.unwrap();
+            if !accepted {
+                return;
+            }
+
+            let mut request = vec![0u8; expected_target.len()];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, expected_target);
+            stream.write_all(&[SOCKS_VERSION, 0, 0, SOCKS_IPV4, 127, 0, 0, 1, 0, 80]).await.unwrap();
+
+            let mut buffer = [0u8; 4];
+            stream.read_exact(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
+        });
+
+        address
+    }
+
+    #[test]
+    fn test_target_authority() {
+        assert_eq!(target_authority("example.com:1234").unwrap(), "example.com:1234");
+        assert_eq!(target_authority("example.com").unwrap(), "example.com:64738");
+        assert_eq!(target_authority("[::1]").unwrap(), "[::1]:64738");
+    }
+
+    #[tokio::test]
+    async fn test_socks5_proxy() {
+        let mut expected_target = vec![SOCKS_VERSION, SOCKS_CONNECT, 0, SOCKS_DOMAIN_NAME, 11];
+        expected_target.extend_from_slice(b"example.com");
+        expected_target.extend_from_slice(&64738u16.to_be_bytes());
+
+        let address = socks5_proxy(expected_target.clone()).await;
+        let proxy = Proxy::socks5(&address).with_credentials("bot", "hello");
+        let mut stream = proxy.connect("example.com:64738").await.unwrap();
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut buffer = [0u8; 4];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        let address = socks5_proxy(expected_target).await;
+        let proxy = Proxy::socks5(&address).with_credentials("bot", "wrong");
+        match proxy.connect("example.com:64738").await {
+            Err(MumbleError::Proxy(_)) => {},
+            result => panic!("unexpected result {:?}", result.map(|_| ()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.starts_with("CONNECT 10.0.0.1:64738 HTTP/1.1\r\n"));
+            assert!(request.contains("Proxy-Authorization: Basic Ym90OmhlbGxv\r\n"));
+
+            // the first tunnelled bytes arrive right behind the headers
+            stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\nwelcome").await.unwrap();
+        });
+
+        let proxy = Proxy::http_connect(&address).with_credentials("bot", "hello");
+        let mut stream = proxy.connect("10.0.0.1:64738").await.unwrap();
+
+        let mut buffer = [0u8; 7];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"welcome");
+    }
+}
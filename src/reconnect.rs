@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+/// Controls if and how the client re-establishes a dropped connection.
+/// Delays grow exponentially from `initial_delay` up to `max_delay`, with a
+/// random `jitter` fraction taken off each one so that many clients do not
+/// reconnect in lockstep.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Between 0 and 1.
+    pub jitter: f64,
+    /// Give up after this many failed attempts, or never when `None`.
+    pub max_attempts: Option<u32>
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None
+        }
+    }
+}
+
+impl ReconnectPolicy {
+
+    /// Delay before the given attempt, starting at 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::random::<f64>();
+
+        Duration::from_secs_f64(delay * (1.0 - jitter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_delay() {
+        let policy = ReconnectPolicy {
+            jitter: 0.0,
+            ..ReconnectPolicy::default()
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(4), Duration::from_secs(8));
+        assert_eq!(policy.delay(100), Duration::from_secs(60));
+
+        let policy = ReconnectPolicy::default();
+        for attempt in 1..10 {
+            let delay = policy.delay(attempt);
+            let ceiling = ReconnectPolicy { jitter: 0.0, ..policy.clone() }.delay(attempt);
+            assert!(delay <= ceiling && delay >= ceiling.mul_f64(0.8));
+        }
+    }
+}
@@ -1,15 +1,62 @@
-enum RejectType {
-    None = 0,
-    WrongVersion = 1,
-    InvalidUsername = 2,
-    WrongUserPW = 3,
-    WrongServerPW = 4,
-    UsernameInUse = 5,
-    ServerFull = 6,
-    NoCertificate = 7,
-    AuthenticatorFail = 8
-}
-struct RejectMessage {
-    reject_type: Option<RejectType>,
-    reason: Option<String>
-}
\ No newline at end of file
+use crate::mumbleproto::Reject;
+
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectType {
+    None = 0,
+    WrongVersion = 1,
+    InvalidUsername = 2,
+    WrongUserPW = 3,
+    WrongServerPW = 4,
+    UsernameInUse = 5,
+    ServerFull = 6,
+    NoCertificate = 7,
+    AuthenticatorFail = 8
+}
+
+impl From<i32> for RejectType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => RejectType::WrongVersion,
+            2 => RejectType::InvalidUsername,
+            3 => RejectType::WrongUserPW,
+            4 => RejectType::WrongServerPW,
+            5 => RejectType::UsernameInUse,
+            6 => RejectType::ServerFull,
+            7 => RejectType::NoCertificate,
+            8 => RejectType::AuthenticatorFail,
+            _ => RejectType::None
+        }
+    }
+}
+
+/// The server refused the connection during authentication.
+#[derive(Debug, Clone)]
+pub struct RejectMessage {
+    pub reject_type: Option<RejectType>,
+    pub reason: Option<String>
+}
+
+impl RejectMessage {
+    pub fn from_message(message: &Reject) -> Self {
+        Self {
+            reject_type: message.r#type.map(RejectType::from),
+            reason: message.reason.clone()
+        }
+    }
+}
+
+impl Display for RejectMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let reject_type = self.reject_type.unwrap_or(RejectType::None);
+
+        match &self.reason {
+            Some(reason) => write!(f, "Rejected by server ({:?}): {}", reject_type, reason),
+            None => write!(f, "Rejected by server ({:?})", reject_type)
+        }
+    }
+}
+
+impl Error for RejectMessage {}
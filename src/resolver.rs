@@ -0,0 +1,322 @@
+use crate::common::MumbleResult;
+use crate::tls::split_address;
+
+use async_trait::async_trait;
+use hickory_resolver::TokioResolver;
+use rand::Rng;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// How long an attempt may run before the next address is tried alongside
+/// it, as recommended by RFC 8305.
+pub const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String
+}
+
+/// Turns host names into addresses. Replace it with a stub in tests, or to
+/// resolve through something other than the system's DNS configuration.
+#[async_trait]
+pub trait Resolver: Send + Sync {
+
+    /// Returns the SRV records for `name`, or an empty list if it has none.
+    async fn lookup_srv(&self, name: &str) -> MumbleResult<Vec<SrvRecord>>;
+
+    async fn lookup_ip(&self, host: &str) -> MumbleResult<Vec<IpAddr>>;
+}
+
+/// SRV records come from the system's DNS servers, addresses from the
+/// operating system's resolver so that `/etc/hosts` and the like apply.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+
+    async fn lookup_srv(&self, name: &str) -> MumbleResult<Vec<SrvRecord>> {
+        let resolver = TokioResolver::builder_tokio()
+            .map_err(|e| io::Error::other(e.to_string()))?
+            .build();
+
+        // a missing record is not an error, the host is simply used as is
+        let lookup = match resolver.srv_lookup(name).await {
+            Ok(lookup) => lookup,
+            Err(_) => return Ok(Vec::new())
+        };
+
+        Ok(lookup.iter()
+            .map(|srv| SrvRecord {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_utf8()
+            })
+            .collect())
+    }
+
+    async fn lookup_ip(&self, host: &str) -> MumbleResult<Vec<IpAddr>> {
+        let addresses = tokio::net::lookup_host((host, 0)).await?;
+        Ok(addresses.map(|address| address.ip()).collect())
+    }
+}
+
+/// Sorts records by priority, shuffling each priority by weight as RFC 2782
+/// describes. Targets of "." mean the service is not offered and are dropped.
+pub(crate) fn order_srv_records(mut records: Vec<SrvRecord>) -> Vec<SrvRecord> {
+
+    records.retain(|record| record.target != "." && !record.target.is_empty());
+    records.sort_by_key(|record| record.priority);
+
+    let mut rng = rand::thread_rng();
+    let mut ordered = Vec::with_capacity(records.len());
+
+    while !records.is_empty() {
+        let priority = records[0].priority;
+        let end = records.iter().position(|record| record.priority != priority).unwrap_or(records.len());
+        let mut group: Vec<SrvRecord> = records.drain(..end).collect();
+
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|record| record.weight as u32).sum();
+
+            let index = match total {
+                0 => 0,
+                total => {
+                    let mut pick = rng.gen_range(0..total);
+                    group.iter()
+                        .position(|record| {
+                            let weight = record.weight as u32;
+                            let selected = pick < weight;
+                            pick = pick.saturating_sub(weight);
+                            selected
+                        })
+                        .unwrap_or(0)
+                }
+            };
+
+            ordered.push(group.remove(index));
+        }
+    }
+
+    ordered
+}
+
+/// Alternates between address families, IPv6 first, so that a broken
+/// family only costs one happy eyeballs delay.
+pub(crate) fn interleave_addresses(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+
+    let (ipv6, ipv4): (Vec<_>, Vec<_>) = addresses.into_iter().partition(|address| address.is_ipv6());
+
+    let mut ipv6 = ipv6.into_iter();
+    let mut ipv4 = ipv4.into_iter();
+    let mut interleaved = Vec::new();
+
+    loop {
+        match (ipv6.next(), ipv4.next()) {
+            (None, None) => return interleaved,
+            (first, second) => interleaved.extend(first.into_iter().chain(second))
+        }
+    }
+}
+
+/// Resolves `address` and connects to the first host that answers. Returns
+/// the stream together with the host name it belongs to, which is the SRV
+/// target when the server publishes one.
+pub(crate) async fn connect(resolver: &dyn Resolver, address: &str, delay: Duration) -> MumbleResult<(TcpStream, String)> {
+
+    let (host, port) = split_address(address)?;
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        let stream = happy_eyeballs(vec![SocketAddr::new(ip, port)], delay).await?;
+        return Ok((stream, host.to_owned()));
+    }
+
+    let srv_records = order_srv_records(resolver.lookup_srv(&format!("_mumble._tcp.{}", host)).await?);
+
+    let mut last_error = None;
+
+    for record in &srv_records {
+        let target = record.target.trim_end_matches('.');
+
+        let result = match resolver.lookup_ip(target).await {
+            Ok(ips) => connect_ips(ips, record.port, delay).await,
+            Err(e) => Err(e)
+        };
+
+        match result {
+            Ok(stream) => return Ok((stream, target.to_owned())),
+            Err(e) => last_error = Some(e)
+        }
+    }
+
+    if let Some(e) = last_error {
+        return Err(e);
+    }
+
+    let stream = connect_ips(resolver.lookup_ip(host).await?, port, delay).await?;
+
+    Ok((stream, host.to_owned()))
+}
+
+async fn connect_ips(ips: Vec<IpAddr>, port: u16, delay: Duration) -> MumbleResult<TcpStream> {
+    let addresses = ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect();
+    happy_eyeballs(interleave_addresses(addresses), delay).await
+}
+
+/// Starts a connection attempt to each address in turn, `delay` apart or as
+/// soon as the previous attempt fails, and keeps the first that succeeds.
+pub(crate) async fn happy_eyeballs(addresses: Vec<SocketAddr>, delay: Duration) -> MumbleResult<TcpStream> {
+
+    let (tx, mut rx) = mpsc::channel(addresses.len().max(1));
+    let mut attempts: Vec<JoinHandle<()>> = Vec::new();
+    let mut addresses = addresses.into_iter();
+    let mut pending = 0;
+    let mut last_error = None;
+
+    let result = loop {
+        if let Some(address) = addresses.next() {
+            let tx = tx.clone();
+            attempts.push(tokio::spawn(async move {
+                tx.send(TcpStream::connect(address).await).await.unwrap_or_default();
+            }));
+            pending += 1;
+        } else if pending == 0 {
+            break Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Host has no addresses")).into());
+        }
+
+        // with nothing left to start, wait for the outstanding attempts
+        let wait = match addresses.len() {
+            0 => Duration::MAX,
+            _ => delay
+        };
+
+        match tokio::time::timeout(wait, rx.recv()).await {
+            Ok(Some(Ok(stream))) => break Ok(stream),
+            Ok(Some(Err(e))) => {
+                pending -= 1;
+                last_error = Some(e);
+            },
+            Ok(None) | Err(_) => {}
+        }
+    };
+
+    for attempt in attempts {
+        attempt.abort();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::MumbleError;
+
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    struct StubResolver {
+        srv: Vec<SrvRecord>,
+        hosts: HashMap<&'static str, Vec<IpAddr>>
+    }
+
+    #[async_trait]
+    impl Resolver for StubResolver {
+        async fn lookup_srv(&self, name: &str) -> MumbleResult<Vec<SrvRecord>> {
+            assert_eq!(name, "_mumble._tcp.example.com");
+            Ok(self.srv.clone())
+        }
+
+        async fn lookup_ip(&self, host: &str) -> MumbleResult<Vec<IpAddr>> {
+            match self.hosts.get(host) {
+                Some(ips) => Ok(ips.clone()),
+                None => Err(MumbleError::Io(io::Error::new(io::ErrorKind::NotFound, host.to_owned())))
+            }
+        }
+    }
+
+    fn srv(priority: u16, weight: u16, port: u16, target: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target: target.to_owned()
+        }
+    }
+
+    #[test]
+    fn test_order_srv_records() {
+        let records = vec![srv(20, 0, 3, "c."), srv(10, 0, 2, "b."), srv(10, 100, 1, "a."), srv(5, 0, 0, ".")];
+        let ordered = order_srv_records(records);
+
+        let targets: Vec<_> = ordered.iter().map(|record| record.target.as_str()).collect();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(targets[2], "c.");
+        assert!(targets[..2].contains(&"a.") && targets[..2].contains(&"b."));
+
+        let addresses = vec!["127.0.0.1:1".parse().unwrap(), "127.0.0.2:1".parse().unwrap(), "[::1]:1".parse().unwrap()];
+        let interleaved: Vec<SocketAddr> = interleave_addresses(addresses);
+        assert!(interleaved[0].is_ipv6());
+        assert_eq!(interleaved[2], "127.0.0.2:1".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_connect_follows_srv() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // a closed port in front of the working one
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let mut hosts = HashMap::new();
+        hosts.insert("primary.example.com", vec!["127.0.0.1".parse().unwrap()]);
+        hosts.insert("backup.example.com", vec!["127.0.0.1".parse().unwrap()]);
+
+        let resolver = StubResolver {
+            srv: vec![srv(1, 0, closed_port, "primary.example.com."), srv(2, 0, port, "backup.example.com.")],
+            hosts
+        };
+
+        let (stream, host) = connect(&resolver, "example.com:64738", HAPPY_EYEBALLS_DELAY).await.unwrap();
+        assert_eq!(host, "backup.example.com");
+        assert_eq!(stream.peer_addr().unwrap().port(), port);
+
+        let resolver = StubResolver {
+            srv: Vec::new(),
+            hosts: vec![("example.com", vec!["127.0.0.1".parse().unwrap()])].into_iter().collect()
+        };
+
+        let (_, host) = connect(&resolver, &format!("example.com:{}", port), HAPPY_EYEBALLS_DELAY).await.unwrap();
+        assert_eq!(host, "example.com");
+    }
+
+    #[tokio::test]
+    async fn test_happy_eyeballs_skips_refused_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused = closed.local_addr().unwrap();
+        drop(closed);
+
+        // a refused attempt starts the next one without waiting out the delay
+        let started = std::time::Instant::now();
+        let stream = happy_eyeballs(vec![refused, open], Duration::from_secs(30)).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        assert!(happy_eyeballs(vec![refused], HAPPY_EYEBALLS_DELAY).await.is_err());
+        assert!(happy_eyeballs(Vec::new(), HAPPY_EYEBALLS_DELAY).await.is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::mumbleproto::Ping;
+
+use std::time::Duration;
+
+/// Latency of the control connection, measured from echoed pings, and the
+/// server's counters for the voice packets it received from us.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Number of pings the server has echoed.
+    pub tcp_packets: u32,
+    /// Running average of the round trip time, in milliseconds.
+    pub tcp_ping_avg: f32,
+    /// Running variance of the round trip time, in squared milliseconds.
+    pub tcp_ping_var: f32,
+    pub last_round_trip: Option<Duration>,
+    /// Voice packets that arrived in time.
+    pub good: u32,
+    pub late: u32,
+    pub lost: u32,
+    /// Times the server had to resynchronize its jitter buffer.
+    pub resync: u32,
+    // sum of squared differences from the mean, see record_round_trip
+    squared_deviation: f64
+}
+
+impl ConnectionStats {
+
+    /// Adds one round trip to the average and variance (Welford's method).
+    pub(crate) fn record_round_trip(&mut self, round_trip: Duration) {
+        let milliseconds = round_trip.as_secs_f64() * 1000.0;
+
+        self.tcp_packets += 1;
+        self.last_round_trip = Some(round_trip);
+
+        let mean = f64::from(self.tcp_ping_avg);
+        let delta = milliseconds - mean;
+        let mean = mean + delta / f64::from(self.tcp_packets);
+        self.squared_deviation += delta * (milliseconds - mean);
+
+        self.tcp_ping_avg = mean as f32;
+        self.tcp_ping_var = (self.squared_deviation / f64::from(self.tcp_packets)) as f32;
+    }
+
+    /// Takes over the counters the server reports in its ping reply.
+    pub(crate) fn record_server_counters(&mut self, ping: &Ping) {
+        self.good = ping.good.unwrap_or(self.good);
+        self.late = ping.late.unwrap_or(self.late);
+        self.lost = ping.lost.unwrap_or(self.lost);
+        self.resync = ping.resync.unwrap_or(self.resync);
+    }
+
+    /// The running average as a duration.
+    pub fn average_round_trip(&self) -> Duration {
+        Duration::from_secs_f32(self.tcp_ping_avg / 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut stats = ConnectionStats::default();
+
+        for milliseconds in &[10, 20, 30, 40] {
+            stats.record_round_trip(Duration::from_millis(*milliseconds));
+        }
+
+        assert_eq!(stats.tcp_packets, 4);
+        assert!((stats.tcp_ping_avg - 25.0).abs() < 0.001);
+        assert!((stats.tcp_ping_var - 125.0).abs() < 0.001);
+        assert_eq!(stats.last_round_trip, Some(Duration::from_millis(40)));
+        assert_eq!(stats.average_round_trip(), Duration::from_millis(25));
+
+        let ping = Ping {
+            good: Some(100),
+            late: Some(2),
+            lost: Some(1),
+            ..Ping::default()
+        };
+        stats.record_server_counters(&ping);
+        assert_eq!((stats.good, stats.late, stats.lost, stats.resync), (100, 2, 1, 0));
+    }
+}
@@ -0,0 +1,355 @@
+use crate::common::MumbleResult;
+use crate::errors::{CertificateMismatchError, MumbleError};
+use crate::identity::Identity;
+use crate::transport::BoxedTransport;
+use crate::url::DEFAULT_PORT;
+
+use tokio::net::TcpStream;
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// OpenSSL wins when both backends are enabled
+#[cfg(feature = "openssl-tls")]
+pub(crate) mod openssl_tls;
+#[cfg(feature = "openssl-tls")]
+pub(crate) use self::openssl_tls as backend;
+
+#[cfg(all(feature = "rustls-tls", not(feature = "openssl-tls")))]
+pub(crate) mod rustls_tls;
+#[cfg(all(feature = "rustls-tls", not(feature = "openssl-tls")))]
+pub(crate) use self::rustls_tls as backend;
+
+/// Decision returned by a [`TrustPolicy`] when a pinned server presents a
+/// different certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustDecision {
+    /// Abort the connection with a [`CertificateMismatchError`].
+    Reject,
+    /// Accept the certificate for this connection only.
+    Accept,
+    /// Accept the certificate and replace the pinned fingerprint.
+    Pin
+}
+
+/// Called with the server address, the pinned fingerprint and the presented
+/// fingerprint whenever they differ.
+pub type TrustPolicy = Arc<dyn Fn(&str, &Fingerprint, &Fingerprint) -> TrustDecision + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub enum ServerVerification {
+    /// Accept any certificate the server presents.
+    #[default]
+    None,
+    /// Verify the certificate chain and hostname against the system roots,
+    /// or against `ca_file` when one is given.
+    CertificateAuthority {
+        ca_file: Option<PathBuf>
+    },
+    /// Trust on first use: remember each server's fingerprint in
+    /// `known_servers` and refuse to connect if it later changes, unless
+    /// `policy` decides otherwise.
+    TrustOnFirstUse {
+        known_servers: PathBuf,
+        policy: Option<TrustPolicy>
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    /// Client certificate presented to the server.
+    pub identity: Option<Identity>,
+    pub verification: ServerVerification,
+    /// Name used for SNI and hostname verification. Defaults to the host
+    /// part of the server address.
+    pub server_name: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    sha1: Vec<u8>,
+    sha256: Vec<u8>
+}
+
+impl Fingerprint {
+
+    /// Fingerprints a DER encoded certificate.
+    pub fn from_der(certificate: &[u8]) -> MumbleResult<Self> {
+        Ok(Self {
+            sha1: backend::sha1(certificate)?,
+            sha256: backend::sha256(certificate)?
+        })
+    }
+
+    pub fn sha1(&self) -> &[u8] {
+        &self.sha1
+    }
+
+    pub fn sha256(&self) -> &[u8] {
+        &self.sha256
+    }
+
+    pub fn sha1_hex(&self) -> String {
+        to_hex(&self.sha1)
+    }
+
+    pub fn sha256_hex(&self) -> String {
+        to_hex(&self.sha256)
+    }
+
+    fn from_hex(sha1: &str, sha256: &str) -> Option<Self> {
+        Some(Self {
+            sha1: from_hex(sha1)?,
+            sha256: from_hex(sha256)?
+        })
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SHA-1 {} / SHA-256 {}", self.sha1_hex(), self.sha256_hex())
+    }
+}
+
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(data: &str) -> Option<Vec<u8>> {
+    data.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None
+        })
+        .collect()
+}
+
+/// Fingerprints of previously seen servers, stored one per line as
+/// `host:port sha1 sha256`.
+pub struct KnownServers {
+    path: PathBuf,
+    servers: HashMap<String, Fingerprint>
+}
+
+impl KnownServers {
+
+    pub fn load<P: Into<PathBuf>>(path: P) -> MumbleResult<Self> {
+        let path = path.into();
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into())
+        };
+
+        let mut servers = HashMap::new();
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let fingerprint = match fields.as_slice() {
+                [_, sha1, sha256] => Fingerprint::from_hex(sha1, sha256),
+                _ => None
+            };
+
+            match fingerprint {
+                Some(fingerprint) => servers.insert(fields[0].to_owned(), fingerprint),
+                None => return Err(MumbleError::InvalidCertificate(format!("Malformed entry for {} in known servers file", fields[0])))
+            };
+        }
+
+        Ok(Self {
+            path,
+            servers
+        })
+    }
+
+    pub fn save(&self) -> MumbleResult<()> {
+        let mut servers: Vec<_> = self.servers.iter().collect();
+        servers.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut contents = String::new();
+        for (server, fingerprint) in servers {
+            contents.push_str(&format!("{} {} {}\n", server, fingerprint.sha1_hex(), fingerprint.sha256_hex()));
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, contents)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, server: &str) -> Option<&Fingerprint> {
+        self.servers.get(server)
+    }
+
+    pub fn insert(&mut self, server: &str, fingerprint: Fingerprint) {
+        self.servers.insert(server.to_owned(), fingerprint);
+    }
+
+    pub fn remove(&mut self, server: &str) -> Option<Fingerprint> {
+        self.servers.remove(server)
+    }
+
+    /// Checks `presented` against the pinned fingerprint for `server`,
+    /// pinning it if the server has not been seen before.
+    pub fn verify(&mut self, server: &str, presented: &Fingerprint, policy: Option<&TrustPolicy>) -> MumbleResult<()> {
+
+        let known = match self.servers.get(server) {
+            Some(known) if known == presented => return Ok(()),
+            Some(known) => known.clone(),
+            None => {
+                self.insert(server, presented.clone());
+                return self.save();
+            }
+        };
+
+        let decision = match policy {
+            Some(policy) => policy(server, &known, presented),
+            None => TrustDecision::Reject
+        };
+
+        match decision {
+            TrustDecision::Accept => Ok(()),
+            TrustDecision::Pin => {
+                self.insert(server, presented.clone());
+                self.save()
+            },
+            TrustDecision::Reject => Err(MumbleError::CertificateMismatch(CertificateMismatchError {
+                server: server.to_owned(),
+                known,
+                presented: presented.clone()
+            }))
+        }
+    }
+}
+
+/// Returns the host part of a `host:port` address, handling bracketed IPv6
+/// literals.
+pub(crate) fn host_from_address(address: &str) -> &str {
+    if let Some(rest) = address.strip_prefix('[') {
+        if let Some(end) = rest.find(']') {
+            return &rest[..end];
+        }
+    }
+
+    match address.rsplit_once(':') {
+        Some((host, _)) if !host.contains(':') => host,
+        _ => address
+    }
+}
+
+/// Splits a `host:port` address into the host, as [`host_from_address`]
+/// returns it, and the port, which defaults to the Mumble port when left out.
+pub(crate) fn split_address(address: &str) -> MumbleResult<(&str, u16)> {
+    let port = match address.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').and_then(|(_, rest)| rest.strip_prefix(':')),
+        // a bare IPv6 address has colons but no port
+        None => address.rsplit_once(':').filter(|(host, _)| !host.contains(':')).map(|(_, port)| port)
+    };
+
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| {
+            io::Error::new(ErrorKind::InvalidInput, format!("Invalid port in server address {}", address))
+        })?,
+        None => DEFAULT_PORT
+    };
+
+    Ok((host_from_address(address), port))
+}
+
+/// Runs the handshake over `tcp_stream`. `host` is the name the stream was
+/// resolved from and is verified unless a server name is configured, while
+/// known fingerprints stay keyed by the address the user asked for.
+pub(crate) async fn connect(address: &str, host: &str, tcp_stream: TcpStream, config: &TlsConfig) -> MumbleResult<BoxedTransport> {
+
+    let server_name = match &config.server_name {
+        Some(server_name) => server_name.as_str(),
+        None => host
+    };
+
+    let (stream, peer_certificate) = backend::handshake(tcp_stream, server_name, config).await?;
+
+    if let ServerVerification::TrustOnFirstUse { known_servers, policy } = &config.verification {
+        let certificate = match peer_certificate {
+            Some(certificate) => certificate,
+            None => return Err(MumbleError::InvalidCertificate("Server did not present a certificate".to_owned()))
+        };
+
+        let fingerprint = Fingerprint::from_der(&certificate)?;
+        let mut known_servers = KnownServers::load(known_servers)?;
+        known_servers.verify(address, &fingerprint, policy.as_ref())?;
+    }
+
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(seed: u8) -> Fingerprint {
+        Fingerprint {
+            sha1: vec![seed; 20],
+            sha256: vec![seed; 32]
+        }
+    }
+
+    #[test]
+    fn test_host_from_address() {
+        assert_eq!(host_from_address("example.com:64738"), "example.com");
+        assert_eq!(host_from_address("127.0.0.1:64738"), "127.0.0.1");
+        assert_eq!(host_from_address("[::1]:64738"), "::1");
+        assert_eq!(host_from_address("example.com"), "example.com");
+    }
+
+    #[test]
+    fn test_split_address() {
+        assert_eq!(split_address("example.com:1234").unwrap(), ("example.com", 1234));
+        assert_eq!(split_address("example.com").unwrap(), ("example.com", DEFAULT_PORT));
+        assert_eq!(split_address("[::1]:1234").unwrap(), ("::1", 1234));
+        assert_eq!(split_address("[::1]").unwrap(), ("::1", DEFAULT_PORT));
+        assert_eq!(split_address("::1").unwrap(), ("::1", DEFAULT_PORT));
+        assert!(split_address("example.com:6473x").is_err());
+    }
+
+    #[test]
+    fn test_known_servers_trust_on_first_use() {
+        let path = std::env::temp_dir().join(format!("mumble-rs-known-servers-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut known_servers = KnownServers::load(&path).unwrap();
+        known_servers.verify("example.com:64738", &fingerprint(1), None).unwrap();
+
+        let mut known_servers = KnownServers::load(&path).unwrap();
+        assert_eq!(known_servers.get("example.com:64738"), Some(&fingerprint(1)));
+        known_servers.verify("example.com:64738", &fingerprint(1), None).unwrap();
+
+        let error = known_servers.verify("example.com:64738", &fingerprint(2), None).unwrap_err();
+        match error {
+            MumbleError::CertificateMismatch(error) => {
+                assert_eq!(error.known, fingerprint(1));
+                assert_eq!(error.presented, fingerprint(2));
+            },
+            error => panic!("unexpected error {}", error)
+        }
+
+        let policy: TrustPolicy = Arc::new(|_, _, _| TrustDecision::Pin);
+        known_servers.verify("example.com:64738", &fingerprint(2), Some(&policy)).unwrap();
+        assert_eq!(KnownServers::load(&path).unwrap().get("example.com:64738"), Some(&fingerprint(2)));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
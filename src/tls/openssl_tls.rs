@@ -0,0 +1,262 @@
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::identity::{Identity, KeyType, CERTIFICATE_VALIDITY_DAYS};
+use crate::tls::{ServerVerification, TlsConfig};
+use crate::transport::BoxedTransport;
+
+use openssl::asn1::{Asn1Integer, Asn1Time};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::stack::Stack;
+use openssl::ssl::{SslConnector, SslConnectorBuilder, SslMethod, SslVerifyMode};
+use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectKeyIdentifier};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509, X509NameBuilder};
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+
+use std::fs;
+use std::pin::Pin;
+
+pub(crate) fn sha1(data: &[u8]) -> MumbleResult<Vec<u8>> {
+    Ok(hash(MessageDigest::sha1(), data)?.to_vec())
+}
+
+pub(crate) fn sha256(data: &[u8]) -> MumbleResult<Vec<u8>> {
+    Ok(hash(MessageDigest::sha256(), data)?.to_vec())
+}
+
+pub(crate) fn generate_identity(name: &str, key_type: KeyType) -> MumbleResult<Identity> {
+
+    let private_key = match key_type {
+        KeyType::Rsa { bits } => PKey::from_rsa(Rsa::generate(bits)?)?,
+        KeyType::EllipticCurve => {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+            PKey::from_ec_key(EcKey::generate(&group)?)?
+        }
+    };
+
+    let mut subject = X509NameBuilder::new()?;
+    subject.append_entry_by_nid(Nid::COMMONNAME, name)?;
+    let subject = subject.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+    let serial = Asn1Integer::from_bn(&serial)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(CERTIFICATE_VALIDITY_DAYS)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&subject)?;
+    builder.set_issuer_name(&subject)?;
+    builder.set_pubkey(&private_key)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    builder.append_extension(KeyUsage::new().digital_signature().key_encipherment().build()?)?;
+    builder.append_extension(ExtendedKeyUsage::new().client_auth().build()?)?;
+    let subject_key_identifier = SubjectKeyIdentifier::new()
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(subject_key_identifier)?;
+
+    builder.sign(&private_key, MessageDigest::sha256())?;
+
+    Ok(Identity::from_der(builder.build().to_der()?, private_key.private_key_to_pkcs8()?))
+}
+
+pub(crate) fn identity_from_pem(certificate: &[u8], private_key: &[u8]) -> MumbleResult<Identity> {
+
+    // the certificate file may carry intermediates after the leaf
+    let mut certificates = X509::stack_from_pem(certificate)?.into_iter();
+    let certificate = match certificates.next() {
+        Some(certificate) => certificate,
+        None => return Err(MumbleError::InvalidCertificate("No certificate found in PEM data".to_owned()))
+    };
+
+    let private_key = PKey::private_key_from_pem(private_key)?;
+
+    let chain = certificates
+        .map(|certificate| certificate.to_der())
+        .collect::<Result<_, _>>()?;
+
+    Ok(Identity::from_parts(certificate.to_der()?, private_key.private_key_to_pkcs8()?, chain))
+}
+
+pub(crate) fn identity_from_pkcs12(der: &[u8], passphrase: &str) -> MumbleResult<Identity> {
+
+    let pkcs12 = Pkcs12::from_der(der)?;
+    let parsed = pkcs12.parse2(passphrase)?;
+
+    let (certificate, private_key) = match (parsed.cert, parsed.pkey) {
+        (Some(certificate), Some(private_key)) => (certificate, private_key),
+        _ => return Err(MumbleError::InvalidCertificate("PKCS#12 bundle is missing a certificate or private key".to_owned()))
+    };
+
+    let chain = match parsed.ca {
+        Some(chain) => chain.into_iter()
+            .map(|certificate| certificate.to_der())
+            .collect::<Result<_, _>>()?,
+        None => Vec::new()
+    };
+
+    Ok(Identity::from_parts(certificate.to_der()?, private_key.private_key_to_pkcs8()?, chain))
+}
+
+pub(crate) fn identity_to_pkcs12(identity: &Identity, name: &str, passphrase: &str) -> MumbleResult<Vec<u8>> {
+
+    let certificate = X509::from_der(identity.certificate())?;
+    let private_key = PKey::private_key_from_pkcs8(identity.private_key())?;
+
+    let mut builder = Pkcs12::builder();
+    builder.name(name);
+    builder.pkey(&private_key);
+    builder.cert(&certificate);
+
+    if !identity.chain().is_empty() {
+        let mut chain = Stack::new()?;
+        for certificate in identity.chain() {
+            chain.push(X509::from_der(certificate)?)?;
+        }
+        builder.ca(chain);
+    }
+
+    let pkcs12 = builder.build2(passphrase)?;
+
+    Ok(pkcs12.to_der()?)
+}
+
+fn apply_identity(identity: &Identity, connector: &mut SslConnectorBuilder) -> MumbleResult<()> {
+    let certificate = X509::from_der(identity.certificate())?;
+    let private_key = PKey::private_key_from_pkcs8(identity.private_key())?;
+
+    connector.set_certificate(&certificate)?;
+    connector.set_private_key(&private_key)?;
+
+    for certificate in identity.chain() {
+        connector.add_extra_chain_cert(X509::from_der(certificate)?)?;
+    }
+
+    connector.check_private_key()?;
+
+    Ok(())
+}
+
+pub(crate) async fn handshake(tcp_stream: TcpStream, server_name: &str, config: &TlsConfig) -> MumbleResult<(BoxedTransport, Option<Vec<u8>>)> {
+
+    let mut connector = SslConnector::builder(SslMethod::tls())?;
+
+    match &config.verification {
+        ServerVerification::CertificateAuthority { ca_file } => {
+            connector.set_verify(SslVerifyMode::PEER);
+            // the builder starts out with the system roots, which a given
+            // ca_file replaces rather than extends
+            if let Some(ca_file) = ca_file {
+                let mut store = X509StoreBuilder::new()?;
+                for certificate in X509::stack_from_pem(&fs::read(ca_file)?)? {
+                    store.add_cert(certificate)?;
+                }
+                connector.set_verify_cert_store(store.build())?;
+            }
+        },
+        _ => connector.set_verify(SslVerifyMode::NONE)
+    }
+
+    if let Some(identity) = &config.identity {
+        apply_identity(identity, &mut connector)?;
+    }
+
+    let ssl = connector.build()
+        .configure()?
+        .into_ssl(server_name)?;
+
+    let mut stream = SslStream::new(ssl, tcp_stream)?;
+    Pin::new(&mut stream).connect().await?;
+
+    let peer_certificate = match stream.ssl().peer_certificate() {
+        Some(certificate) => Some(certificate.to_der()?),
+        None => None
+    };
+
+    Ok((Box::new(stream), peer_certificate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ssl::SslAcceptor;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use tokio::net::TcpListener;
+
+    // a self-signed authority that is also valid as the certificate of localhost
+    fn certificate_authority(name: &str) -> (X509, PKey<openssl::pkey::Private>) {
+        let private_key = PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap()).unwrap();
+
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+        let subject = subject.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_serial_number(&Asn1Integer::from_bn(&BigNum::from_u32(1).unwrap()).unwrap()).unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_issuer_name(&subject).unwrap();
+        builder.set_pubkey(&private_key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+        builder.append_extension(KeyUsage::new().digital_signature().key_cert_sign().build().unwrap()).unwrap();
+        let subject_alternative_name = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(subject_alternative_name).unwrap();
+        builder.sign(&private_key, MessageDigest::sha256()).unwrap();
+
+        (builder.build(), private_key)
+    }
+
+    async fn handshake_with_ca_file(server: &(X509, PKey<openssl::pkey::Private>), ca: &X509) -> MumbleResult<()> {
+        let ca_file = std::env::temp_dir().join(format!("mumble-rs-ca-{}-{:?}.pem", std::process::id(), std::thread::current().id()));
+        fs::write(&ca_file, ca.to_pem().unwrap()).unwrap();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&server.0).unwrap();
+        acceptor.set_private_key(&server.1).unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let ssl = openssl::ssl::Ssl::new(acceptor.context()).unwrap();
+            let mut stream = SslStream::new(ssl, tcp_stream).unwrap();
+            let _ = Pin::new(&mut stream).accept().await;
+        });
+
+        let config = TlsConfig {
+            verification: ServerVerification::CertificateAuthority { ca_file: Some(ca_file.clone()) },
+            ..TlsConfig::default()
+        };
+        let result = handshake(TcpStream::connect(address).await.unwrap(), "localhost", &config).await;
+
+        fs::remove_file(&ca_file).unwrap();
+        result.map(drop)
+    }
+
+    #[tokio::test]
+    async fn test_ca_file_replaces_system_roots() {
+        let server = certificate_authority("server");
+        let other = certificate_authority("other");
+
+        handshake_with_ca_file(&server, &server.0).await.unwrap();
+        assert!(handshake_with_ca_file(&server, &other.0).await.is_err());
+    }
+}
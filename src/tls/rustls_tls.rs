@@ -0,0 +1,417 @@
+use crate::common::MumbleResult;
+use crate::errors::MumbleError;
+use crate::identity::{Identity, KeyType, CERTIFICATE_VALIDITY_DAYS};
+use crate::tls::{ServerVerification, TlsConfig};
+use crate::transport::BoxedTransport;
+
+use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+use pkcs8::der::asn1::{AnyRef, ObjectIdentifier};
+use pkcs8::der::Encode;
+use pkcs8::{AlgorithmIdentifierRef, PrivateKeyInfo};
+use rcgen::{
+    CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+    SerialNumber, PKCS_ECDSA_P256_SHA256, PKCS_RSA_SHA256
+};
+use rsa::pkcs8::EncodePrivateKey;
+use rsa::RsaPrivateKey;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sec1::EcPrivateKey;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+use std::convert::TryFrom;
+use std::fs;
+use std::iter;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+
+pub(crate) fn sha1(data: &[u8]) -> MumbleResult<Vec<u8>> {
+    Ok(Sha1::digest(data).to_vec())
+}
+
+pub(crate) fn sha256(data: &[u8]) -> MumbleResult<Vec<u8>> {
+    Ok(Sha256::digest(data).to_vec())
+}
+
+pub(crate) fn generate_identity(name: &str, key_type: KeyType) -> MumbleResult<Identity> {
+
+    // ring can sign with RSA keys but not generate them
+    let key_pair = match key_type {
+        KeyType::Rsa { bits } => {
+            let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), bits as usize)
+                .map_err(|e| MumbleError::Tls(Box::new(e)))?;
+            let private_key = private_key.to_pkcs8_der()
+                .map_err(|e| MumbleError::InvalidCertificate(e.to_string()))?;
+
+            KeyPair::from_pkcs8_der_and_sign_algo(&PrivatePkcs8KeyDer::from(private_key.as_bytes()), &PKCS_RSA_SHA256)?
+        },
+        KeyType::EllipticCurve => KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)?
+    };
+
+    let mut subject = DistinguishedName::new();
+    subject.push(DnType::CommonName, name);
+
+    // 128 bit positive serial, like the OpenSSL backend
+    let mut serial = rand::random::<[u8; 16]>();
+    serial[0] &= 0x7f;
+
+    let not_before = OffsetDateTime::now_utc();
+    let not_after = not_before + time::Duration::days(CERTIFICATE_VALIDITY_DAYS.into());
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = subject;
+    params.serial_number = Some(SerialNumber::from_slice(&serial));
+    params.not_before = not_before;
+    params.not_after = not_after;
+    params.is_ca = IsCa::ExplicitNoCa;
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+
+    let certificate = params.self_signed(&key_pair)?;
+
+    Ok(Identity::from_der(certificate.der().to_vec(), key_pair.serialize_der()))
+}
+
+fn read_certificates(pem: &[u8]) -> MumbleResult<Vec<CertificateDer<'static>>> {
+    Ok(rustls_pemfile::certs(&mut &pem[..]).collect::<Result<_, _>>()?)
+}
+
+// wraps PKCS#1 and SEC1 keys so every identity carries a PKCS#8 key
+fn to_pkcs8(private_key: &PrivateKeyDer) -> MumbleResult<Vec<u8>> {
+
+    let curve;
+
+    let (algorithm, private_key) = match private_key {
+        PrivateKeyDer::Pkcs8(private_key) => return Ok(private_key.secret_pkcs8_der().to_vec()),
+        PrivateKeyDer::Pkcs1(private_key) => {
+            let algorithm = AlgorithmIdentifierRef {
+                oid: RSA_ENCRYPTION,
+                parameters: Some(AnyRef::NULL)
+            };
+
+            (algorithm, private_key.secret_pkcs1_der())
+        },
+        PrivateKeyDer::Sec1(private_key) => {
+            let parameters = EcPrivateKey::try_from(private_key.secret_sec1_der())
+                .ok()
+                .and_then(|private_key| private_key.parameters);
+
+            curve = match parameters.and_then(|parameters| parameters.named_curve()) {
+                Some(curve) => curve,
+                None => return Err(MumbleError::InvalidCertificate("EC private key does not name its curve".to_owned()))
+            };
+
+            let algorithm = AlgorithmIdentifierRef {
+                oid: EC_PUBLIC_KEY,
+                parameters: Some(AnyRef::from(&curve))
+            };
+
+            (algorithm, private_key.secret_sec1_der())
+        },
+        _ => return Err(MumbleError::InvalidCertificate("Unsupported private key format".to_owned()))
+    };
+
+    PrivateKeyInfo::new(algorithm, private_key)
+        .to_der()
+        .map_err(|e| MumbleError::InvalidCertificate(e.to_string()))
+}
+
+pub(crate) fn identity_from_pem(certificate: &[u8], private_key: &[u8]) -> MumbleResult<Identity> {
+
+    // the certificate file may carry intermediates after the leaf
+    let mut certificates = read_certificates(certificate)?.into_iter();
+    let certificate = match certificates.next() {
+        Some(certificate) => certificate,
+        None => return Err(MumbleError::InvalidCertificate("No certificate found in PEM data".to_owned()))
+    };
+
+    let private_key = match rustls_pemfile::private_key(&mut &private_key[..])? {
+        Some(private_key) => private_key,
+        None => return Err(MumbleError::InvalidCertificate("No private key found in PEM data".to_owned()))
+    };
+
+    let chain = certificates
+        .map(|certificate| certificate.to_vec())
+        .collect();
+
+    Ok(Identity::from_parts(certificate.to_vec(), to_pkcs8(&private_key)?, chain))
+}
+
+pub(crate) fn identity_from_pkcs12(der: &[u8], passphrase: &str) -> MumbleResult<Identity> {
+
+    let key_store = KeyStore::from_pkcs12(der, passphrase)?;
+
+    let key_chain = match key_store.private_key_chain() {
+        Some((_, key_chain)) => key_chain,
+        None => return Err(MumbleError::InvalidCertificate("PKCS#12 bundle is missing a certificate or private key".to_owned()))
+    };
+
+    // the leaf comes first
+    let mut certificates = key_chain.chain().iter().map(|certificate| certificate.as_der().to_vec());
+    let certificate = match certificates.next() {
+        Some(certificate) => certificate,
+        None => return Err(MumbleError::InvalidCertificate("PKCS#12 bundle is missing a certificate or private key".to_owned()))
+    };
+
+    Ok(Identity::from_parts(certificate, key_chain.key().to_vec(), certificates.collect()))
+}
+
+pub(crate) fn identity_to_pkcs12(identity: &Identity, name: &str, passphrase: &str) -> MumbleResult<Vec<u8>> {
+
+    let chain = iter::once(identity.certificate())
+        .chain(identity.chain().iter().map(Vec::as_slice))
+        .map(Certificate::from_der)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let local_key_id = sha1(identity.certificate())?;
+    let key_chain = PrivateKeyChain::new(identity.private_key(), local_key_id, chain);
+
+    let mut key_store = KeyStore::new();
+    key_store.add_entry(name, KeyStoreEntry::PrivateKeyChain(key_chain));
+
+    Ok(key_store.writer(passphrase).write()?)
+}
+
+fn root_store(ca_file: &Option<PathBuf>) -> MumbleResult<RootCertStore> {
+
+    let certificates = match ca_file {
+        Some(ca_file) => read_certificates(&fs::read(ca_file)?)?,
+        None => rustls_native_certs::load_native_certs().certs
+    };
+
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(certificates);
+
+    Ok(roots)
+}
+
+/// Skips chain and hostname validation but still checks handshake
+/// signatures. Pinned fingerprints are compared once the handshake is done.
+#[derive(Debug)]
+struct AcceptAnyCertificate {
+    provider: Arc<CryptoProvider>
+}
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+pub(crate) async fn handshake(tcp_stream: TcpStream, server_name: &str, config: &TlsConfig) -> MumbleResult<(BoxedTransport, Option<Vec<u8>>)> {
+
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &config.verification {
+        ServerVerification::CertificateAuthority { ca_file } => builder.with_root_certificates(root_store(ca_file)?),
+        _ => builder.dangerous().with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate { provider }))
+    };
+
+    let client_config = match &config.identity {
+        Some(identity) => {
+            let chain = iter::once(identity.certificate())
+                .chain(identity.chain().iter().map(Vec::as_slice))
+                .map(|certificate| CertificateDer::from(certificate.to_vec()))
+                .collect();
+            let private_key = PrivatePkcs8KeyDer::from(identity.private_key().to_vec());
+
+            builder.with_client_auth_cert(chain, private_key.into())?
+        },
+        None => builder.with_no_client_auth()
+    };
+
+    let server_name = ServerName::try_from(server_name.to_owned())
+        .map_err(|e| MumbleError::Tls(Box::new(e)))?;
+
+    let stream = TlsConnector::from(Arc::new(client_config))
+        .connect(server_name, tcp_stream)
+        .await
+        .map_err(|e| MumbleError::Tls(Box::new(e)))?;
+
+    let peer_certificate = stream.get_ref().1
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .map(|certificate| certificate.to_vec());
+
+    Ok((Box::new(stream), peer_certificate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, Issuer};
+    use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+    use rustls::{DistinguishedName, ServerConfig};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    struct ServerCertificate {
+        certificate: CertificateDer<'static>,
+        private_key: Vec<u8>,
+        // the authority that signed it, PEM encoded
+        ca: String
+    }
+
+    // webpki refuses authorities as end entities, so the certificate of
+    // localhost is signed by a separate one
+    fn server_certificate(name: &str) -> ServerCertificate {
+        let ca_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut ca_params = CertificateParams::default();
+        ca_params.distinguished_name.push(DnType::CommonName, name);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::DigitalSignature];
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let certificate = CertificateParams::new(vec!["localhost".to_owned()]).unwrap()
+            .signed_by(&key, &Issuer::new(ca_params, ca_key))
+            .unwrap();
+
+        ServerCertificate {
+            certificate: certificate.der().clone(),
+            private_key: key.serialize_der(),
+            ca: Identity::from_der(ca.der().to_vec(), Vec::new()).certificate_pem()
+        }
+    }
+
+    // asks for a client certificate but takes whatever is presented, like
+    // murmur does
+    #[derive(Debug)]
+    struct AcceptAnyClient {
+        provider: Arc<CryptoProvider>
+    }
+
+    impl ClientCertVerifier for AcceptAnyClient {
+
+        fn client_auth_mandatory(&self) -> bool {
+            false
+        }
+
+        fn root_hint_subjects(&self) -> &[DistinguishedName] {
+            &[]
+        }
+
+        fn verify_client_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _now: UnixTime
+        ) -> Result<ClientCertVerified, rustls::Error> {
+            Ok(ClientCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            certificate: &CertificateDer<'_>,
+            signature: &DigitallySignedStruct
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            crypto::verify_tls12_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            certificate: &CertificateDer<'_>,
+            signature: &DigitallySignedStruct
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            crypto::verify_tls13_signature(message, certificate, signature, &self.provider.signature_verification_algorithms)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.provider.signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    // returns the client certificate the server received
+    async fn handshake_with_ca_file(server: &ServerCertificate, ca: &str, identity: Option<Identity>) -> MumbleResult<Option<Vec<u8>>> {
+        let ca_file = std::env::temp_dir().join(format!("mumble-rs-ca-{}-{:?}.pem", std::process::id(), std::thread::current().id()));
+        fs::write(&ca_file, ca).unwrap();
+
+        let provider = Arc::new(crypto::ring::default_provider());
+        let server_config = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(Arc::new(AcceptAnyClient { provider }))
+            .with_single_cert(vec![server.certificate.clone()], PrivatePkcs8KeyDer::from(server.private_key.clone()).into())
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = tokio::spawn(async move {
+            let (tcp_stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(tcp_stream).await.ok()?;
+
+            stream.get_ref().1
+                .peer_certificates()
+                .and_then(|certificates| certificates.first())
+                .map(|certificate| certificate.to_vec())
+        });
+
+        let config = TlsConfig {
+            identity,
+            verification: ServerVerification::CertificateAuthority { ca_file: Some(ca_file.clone()) },
+            ..TlsConfig::default()
+        };
+        let result = handshake(TcpStream::connect(address).await.unwrap(), "localhost", &config).await;
+
+        fs::remove_file(&ca_file).unwrap();
+        let (_stream, _) = result?;
+        Ok(accepted.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ca_file_and_client_certificate() {
+        let server = server_certificate("server");
+        let other = server_certificate("other");
+        let identity = Identity::generate("bot", KeyType::EllipticCurve).unwrap();
+
+        let presented = handshake_with_ca_file(&server, &server.ca, Some(identity.clone())).await.unwrap();
+        assert_eq!(presented.as_deref(), Some(identity.certificate()));
+
+        assert_eq!(handshake_with_ca_file(&server, &server.ca, None).await.unwrap(), None);
+        assert!(handshake_with_ca_file(&server, &other.ca, None).await.is_err());
+    }
+}
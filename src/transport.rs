@@ -0,0 +1,10 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Any bidirectional byte stream the client can speak the Mumble protocol
+/// over, such as a TLS stream, a tunnelled proxy connection or an in-memory
+/// `tokio::io::duplex` pipe.
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Transport for T {}
+
+pub(crate) type BoxedTransport = Box<dyn Transport>;
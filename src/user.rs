@@ -0,0 +1,201 @@
+use crate::mumbleproto::UserState;
+
+use std::collections::HashMap;
+
+/// Every user connected to the server, keyed by session ID and kept up to
+/// date from `UserState` and `UserRemove` messages.
+#[derive(Debug, Default, Clone)]
+pub struct UserList {
+    users: HashMap<u32, User>
+}
+
+impl UserList {
+
+    /// Applies a `UserState`, creating the user if the session is new. Only
+    /// the fields present in the message change.
+    pub(crate) fn merge(&mut self, state: &UserState) {
+        let session = state.session.unwrap_or_default();
+
+        self.users.entry(session)
+            .or_insert_with(|| User::new(session))
+            .merge(state);
+    }
+
+    pub(crate) fn remove(&mut self, session: u32) -> Option<User> {
+        self.users.remove(&session)
+    }
+
+    pub fn get(&self, session: u32) -> Option<&User> {
+        self.users.get(&session)
+    }
+
+    /// Names are unique on a server, so at most one user matches.
+    pub fn find(&self, name: &str) -> Option<&User> {
+        self.users.values().find(|user| user.name == name)
+    }
+
+    /// Users currently in channel `channel_id`, ordered by session ID.
+    pub fn in_channel(&self, channel_id: u32) -> Vec<&User> {
+        let mut users: Vec<&User> = self.users.values()
+            .filter(|user| user.channel_id == channel_id)
+            .collect();
+
+        users.sort_by_key(|user| user.session);
+        users
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &User> {
+        self.users.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct User {
+    pub session: u32,
+    pub name: String,
+    /// Set for registered users only.
+    pub user_id: Option<u32>,
+    pub channel_id: u32,
+    /// Muted by an admin.
+    pub mute: bool,
+    /// Deafened by an admin.
+    pub deaf: bool,
+    /// Muted by the server, e.g. for lacking the speak permission.
+    pub suppress: bool,
+    pub self_mute: bool,
+    pub self_deaf: bool,
+    pub recording: bool,
+    pub priority_speaker: bool,
+    /// Only sent for short comments, longer ones are announced by their
+    /// hash and have to be requested.
+    pub comment: Option<String>,
+    pub comment_hash: Option<Vec<u8>>,
+    pub texture_hash: Option<Vec<u8>>,
+    /// SHA-1 hash of the user's certificate.
+    pub hash: Option<String>
+}
+
+impl User {
+
+    pub fn new(session: u32) -> Self {
+        Self {
+            session,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn merge(&mut self, state: &UserState) {
+
+        if let Some(name) = &state.name {
+            self.name = name.clone();
+        }
+        if let Some(user_id) = state.user_id {
+            self.user_id = Some(user_id);
+        }
+        if let Some(channel_id) = state.channel_id {
+            self.channel_id = channel_id;
+        }
+
+        self.mute = state.mute.unwrap_or(self.mute);
+        self.deaf = state.deaf.unwrap_or(self.deaf);
+        self.suppress = state.suppress.unwrap_or(self.suppress);
+        self.self_mute = state.self_mute.unwrap_or(self.self_mute);
+        self.self_deaf = state.self_deaf.unwrap_or(self.self_deaf);
+        self.recording = state.recording.unwrap_or(self.recording);
+        self.priority_speaker = state.priority_speaker.unwrap_or(self.priority_speaker);
+
+        if let Some(comment) = &state.comment {
+            self.comment = Some(comment.clone());
+        }
+        if let Some(comment_hash) = &state.comment_hash {
+            // a new hash without the text means the old text is outdated
+            if state.comment.is_none() && self.comment_hash.as_ref() != Some(comment_hash) {
+                self.comment = None;
+            }
+            self.comment_hash = Some(comment_hash.clone());
+        }
+        if let Some(texture_hash) = &state.texture_hash {
+            self.texture_hash = Some(texture_hash.clone());
+        }
+        if let Some(hash) = &state.hash {
+            self.hash = Some(hash.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_user_state() {
+        let mut users = UserList::default();
+
+        users.merge(&UserState {
+            session: Some(5),
+            name: Some("alice".to_owned()),
+            user_id: Some(12),
+            channel_id: Some(3),
+            comment: Some("hi".to_owned()),
+            hash: Some("abcd".to_owned()),
+            ..UserState::default()
+        });
+
+        // a delta only touches the fields it carries
+        users.merge(&UserState {
+            session: Some(5),
+            self_mute: Some(true),
+            suppress: Some(true),
+            ..UserState::default()
+        });
+
+        let alice = users.get(5).unwrap();
+        assert_eq!(alice.name, "alice");
+        assert_eq!(alice.user_id, Some(12));
+        assert_eq!(alice.channel_id, 3);
+        assert!(alice.self_mute && alice.suppress && !alice.self_deaf);
+        assert_eq!(alice.comment.as_deref(), Some("hi"));
+        assert_eq!(alice.hash.as_deref(), Some("abcd"));
+
+        users.merge(&UserState {
+            session: Some(5),
+            comment_hash: Some(vec![1, 2, 3]),
+            ..UserState::default()
+        });
+        assert_eq!(users.get(5).unwrap().comment, None);
+        assert_eq!(users.get(5).unwrap().comment_hash, Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_user_lookup() {
+        let mut users = UserList::default();
+
+        for (session, name, channel_id) in &[(3, "carol", 1), (1, "alice", 1), (2, "bob", 0)] {
+            users.merge(&UserState {
+                session: Some(*session),
+                name: Some((*name).to_owned()),
+                channel_id: Some(*channel_id),
+                ..UserState::default()
+            });
+        }
+
+        assert_eq!(users.len(), 3);
+        assert_eq!(users.find("bob").map(|user| user.session), Some(2));
+        assert!(users.find("dave").is_none());
+
+        let sessions: Vec<u32> = users.in_channel(1).iter().map(|user| user.session).collect();
+        assert_eq!(sessions, vec![1, 3]);
+
+        assert_eq!(users.remove(1).map(|user| user.name), Some("alice".to_owned()));
+        assert!(users.get(1).is_none());
+        assert_eq!(users.in_channel(1).len(), 1);
+    }
+}